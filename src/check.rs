@@ -0,0 +1,608 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{bail, Result};
+use hyper::{http::HeaderValue, Uri};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+    cli::ConfigFormat,
+    config::{
+        eval::Reference,
+        feeder::Feeder,
+        format::{self, Expression, Segment},
+        walk::{Field, Key, Location},
+        HammerFile,
+    },
+};
+
+struct Problem {
+    location: Location,
+    message: String,
+}
+
+/// Statically verifies a configuration file without sending any requests.
+pub fn check(path: &Path, format: ConfigFormat, vars: &[(String, String)]) -> Result<ExitCode> {
+    let text = HammerFile::read(path)?;
+    let format = HammerFile::format(path, format);
+
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let config = match HammerFile::parse(&text, format, base) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("\x1b[31;1merror\x1b[0m: {error:#}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let problems = problems(&config, base, vars);
+    let mut sources = Sources::new(path, text, format);
+
+    for problem in problems.iter() {
+        eprintln!("\x1b[31;1merror\x1b[0m: {}", problem.message);
+        sources.print(&config, &problem.location);
+        eprintln!();
+    }
+
+    if problems.is_empty() {
+        eprintln!("No problems found");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        eprintln!("Found {} problem(s)", problems.len());
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn problems(config: &HammerFile, base: &Path, vars: &[(String, String)]) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut report = |location: Location, message: String| {
+        // Global headers and cookies are copied into every entry so the same problem may be
+        // reported many times.
+        if !problems
+            .iter()
+            .any(|p: &Problem| p.message == message && is_global_copy(&p.location, &location))
+        {
+            problems.push(Problem { location, message });
+        }
+    };
+
+    let mut feeders = HashMap::new();
+    for (name, info) in config.feeders.iter() {
+        match Feeder::load(name.clone(), info.clone(), base) {
+            Ok(feeder) => {
+                feeders.insert(name.as_str(), feeder);
+            }
+            Err(error) => report(
+                Location::default().join("feeders").join(name),
+                format!("{error:#}"),
+            ),
+        }
+    }
+
+    config.walk(&mut |location, field| {
+        let (fmtstr, in_response, in_request) = match field {
+            Field::Constant(value, kind) => {
+                if let Some(message) = check_static(kind(value), value) {
+                    report(location, message);
+                }
+                return;
+            }
+            Field::Formatted(fmtstr) => (fmtstr, false, false),
+            Field::RequestPart(fmtstr) | Field::Header(fmtstr) | Field::Uri(fmtstr) => {
+                (fmtstr, false, true)
+            }
+            Field::ResponseFormat(fmtstr) if format::is_template(fmtstr) => (fmtstr, true, false),
+            Field::ResponseFormat(fmtstr) => {
+                if let Err(error) = format::format_one(fmtstr.to_string(), "") {
+                    report(location, error.to_string());
+                }
+                return;
+            }
+        };
+
+        let segments = match format::parse(fmtstr) {
+            Ok(segments) => segments,
+            Err(error) => {
+                report(location, format!("Invalid format string: {error}"));
+                return;
+            }
+        };
+
+        let mut static_value = Some(String::new());
+        for segment in segments.iter() {
+            let spec = match segment {
+                Segment::Literal(literal) => {
+                    if let Some(ref mut value) = static_value {
+                        value.push_str(literal);
+                    }
+                    continue;
+                }
+                Segment::Spec(spec) => spec,
+            };
+            static_value = None;
+
+            let expression = match Expression::parse(spec) {
+                Ok(expression) => expression,
+                Err(error) => {
+                    report(location.clone(), error.to_string());
+                    continue;
+                }
+            };
+
+            match Reference::parse(expression.reference) {
+                Ok(Reference::Resource(resource)) => {
+                    if !config.resources.contains_key(resource) {
+                        let suggestion = closest(resource, config.resources.keys())
+                            .map(|x| format!(", did you mean {x}?"))
+                            .unwrap_or_default();
+                        report(
+                            location.clone(),
+                            format!("Resource {resource} does not exist{suggestion}"),
+                        );
+                    }
+                }
+                Ok(Reference::Feeder { name, column }) => match feeders.get(name) {
+                    Some(feeder) if !feeder.has_column(column) => report(
+                        location.clone(),
+                        format!("Not every row of feeder {name} has a column named {column}"),
+                    ),
+                    Some(_) => (),
+                    None if config.feeders.contains_key(name) => (),
+                    None => report(location.clone(), format!("Feeder {name} does not exist")),
+                },
+                Ok(Reference::Env {
+                    name,
+                    default: None,
+                }) if std::env::var_os(name).is_none() => report(
+                    location.clone(),
+                    format!("Environment variable {name} is not set and has no default"),
+                ),
+                Ok(Reference::Env { .. }) => (),
+                Ok(Reference::Var(name)) => {
+                    if !vars.iter().any(|(n, _)| n == name) {
+                        report(
+                            location.clone(),
+                            format!("Variable {name} is not set, pass it using --set {name}=VALUE"),
+                        )
+                    }
+                }
+                Ok(Reference::Body) if in_request => (),
+                Ok(Reference::Body) if !in_response => report(
+                    location.clone(),
+                    "body can only be used in the format of a response or in the uri, query, \
+                     headers and cookies of a request"
+                        .to_string(),
+                ),
+                Ok(
+                    Reference::Value | Reference::Body | Reference::Status | Reference::Header(_),
+                ) => {
+                    if !in_response {
+                        report(
+                            location.clone(),
+                            format!(
+                                "{} can only be used in the format of a response",
+                                expression.reference
+                            ),
+                        )
+                    }
+                }
+                Err(error) => report(location.clone(), error.to_string()),
+            }
+        }
+
+        if let Some(message) = static_value.and_then(|value| check_static(field, &value)) {
+            report(location, message);
+        }
+    });
+
+    for (cycle, location) in cycles(config) {
+        report(
+            location,
+            format!("Resources form a cycle: {}", cycle.join(" -> ")),
+        );
+    }
+
+    problems
+}
+
+/// Fails if resources refer to each other in a cycle, since they could never be evaluated.
+pub fn reject_cycles(config: &HammerFile) -> Result<()> {
+    match cycles(config).first() {
+        Some((cycle, location)) => bail!(
+            "Resources form a cycle: {} (at {location})",
+            cycle.join(" -> ")
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Finds all cycles in the resource dependency graph along with the location of the reference
+/// from the first resource of a cycle to the second one.
+fn cycles(config: &HammerFile) -> Vec<(Vec<&str>, Location)> {
+    let dependencies = dependencies(config);
+    find_cycles(&dependencies)
+        .into_iter()
+        .map(|cycle| {
+            let (_, location) = dependencies[cycle[0]]
+                .iter()
+                .find(|(to, _)| *to == cycle[1])
+                .unwrap();
+            let location = location.clone();
+            (cycle, location)
+        })
+        .collect()
+}
+
+/// Returns the resources that every resource refers to, along with where they are referenced.
+fn dependencies(config: &HammerFile) -> BTreeMap<&str, Vec<(&str, Location)>> {
+    let mut dependencies = BTreeMap::<&str, Vec<(&str, Location)>>::new();
+    config.walk(&mut |location, field| {
+        let fmtstr = match field {
+            Field::Formatted(fmtstr)
+            | Field::RequestPart(fmtstr)
+            | Field::Header(fmtstr)
+            | Field::Uri(fmtstr) => fmtstr,
+            Field::ResponseFormat(fmtstr) if format::is_template(fmtstr) => fmtstr,
+            Field::ResponseFormat(_) | Field::Constant(..) => return,
+        };
+        let Some(Key::Name(from)) = resource_name(&location) else {
+            return;
+        };
+        let from = config.resources.get_key_value(from).unwrap().0.as_str();
+
+        for segment in format::parse(fmtstr).into_iter().flatten() {
+            let Segment::Spec(spec) = segment else {
+                continue;
+            };
+            let Ok(expression) = Expression::parse(&spec) else {
+                continue;
+            };
+            if let Ok(Reference::Resource(resource)) = Reference::parse(expression.reference) {
+                if let Some((resource, _)) = config.resources.get_key_value(resource) {
+                    dependencies
+                        .entry(from)
+                        .or_default()
+                        .push((resource.as_str(), location.clone()));
+                }
+            }
+        }
+    });
+    dependencies
+}
+
+/// The files problems are shown in, along with their syntax trees if they are TOML files.
+struct Sources<'a> {
+    path: &'a Path,
+    /// Keyed by the included file, `None` is the checked file itself.
+    trees: HashMap<Option<PathBuf>, Option<(String, Node)>>,
+}
+
+impl<'a> Sources<'a> {
+    fn new(path: &'a Path, text: String, format: ConfigFormat) -> Self {
+        // Only TOML configurations can be annotated with the source of a problem
+        let tree = match format {
+            ConfigFormat::Auto | ConfigFormat::Toml => toml::from_str::<Node>(&text).ok(),
+            ConfigFormat::Yaml | ConfigFormat::Json => None,
+        };
+        Self {
+            path,
+            trees: HashMap::from([(None, tree.map(|tree| (text, tree)))]),
+        }
+    }
+
+    fn load(&mut self, file: &Option<PathBuf>) {
+        let Some(path) = file else { return };
+        self.trees.entry(file.clone()).or_insert_with(|| {
+            if HammerFile::format(path, ConfigFormat::Auto) != ConfigFormat::Toml {
+                return None;
+            }
+            let text = std::fs::read_to_string(path).ok()?;
+            let tree = toml::from_str::<Node>(&text).ok()?;
+            Some((text, tree))
+        });
+    }
+
+    /// Prints the span of `location`, in the file the value at `location` was defined in.
+    fn print(&mut self, config: &HammerFile, location: &Location) {
+        let candidates = origins(config, location);
+        for (file, _) in candidates.iter() {
+            self.load(file);
+        }
+
+        let find = |i: usize, partial| {
+            let (file, location) = &candidates[i];
+            let (text, tree) = self.trees[file].as_ref()?;
+            Some((i, text, tree.find(&location.0, partial)?))
+        };
+        let found = (0..candidates.len())
+            .find_map(|i| find(i, false))
+            .or_else(|| find(0, true));
+
+        match found {
+            Some((i, text, span)) => {
+                let (file, location) = &candidates[i];
+                print_span(file.as_deref().unwrap_or(self.path), text, span, location)
+            }
+            None => {
+                let (file, location) = &candidates[0];
+                let path = file.as_deref().unwrap_or(self.path);
+                eprintln!("  --> {} ({location})", path.display());
+            }
+        }
+    }
+}
+
+/// Returns the files and locations within them that the value at `location` may have been
+/// defined at, most specific first.
+fn origins(config: &HammerFile, location: &Location) -> Vec<(Option<PathBuf>, Location)> {
+    match location.0.as_slice() {
+        [Key::Name(hammer), Key::Index(i), rest @ ..] if hammer == "hammer" => {
+            let origin = &config.hammer[*i].origin;
+            let mut candidates = vec![(
+                origin.file.clone(),
+                Location(
+                    [Key::Name("hammer".to_string()), Key::Index(origin.index)]
+                        .into_iter()
+                        .chain(rest.iter().cloned())
+                        .collect(),
+                ),
+            )];
+            for template in origin.templates.iter() {
+                candidates.push((
+                    config.template_files.get(template).cloned(),
+                    Location(
+                        [
+                            Key::Name("templates".to_string()),
+                            Key::Name(template.clone()),
+                        ]
+                        .into_iter()
+                        .chain(rest.iter().cloned())
+                        .collect(),
+                    ),
+                ));
+            }
+            // Entries inherit global headers and cookies
+            candidates.push((origin.file.clone(), Location(rest.to_vec())));
+            candidates.push((None, Location(rest.to_vec())));
+            candidates
+        }
+        [Key::Name(resources), Key::Name(name), ..] if resources == "resources" => {
+            vec![(config.resource_files.get(name).cloned(), location.clone())]
+        }
+        _ => vec![(None, location.clone())],
+    }
+}
+
+/// Checks the value of a field that does not interpolate anything, returns what is wrong with it.
+fn check_static(field: Field, value: &str) -> Option<String> {
+    match field {
+        Field::Header(_) if HeaderValue::try_from(value).is_err() => {
+            Some("Not a valid header value".to_string())
+        }
+        Field::Uri(_) => match value.parse::<Uri>() {
+            Ok(uri) if uri.scheme().is_some() && uri.authority().is_some() => None,
+            _ => Some(format!("{value} is not an absolute uri")),
+        },
+        _ => None,
+    }
+}
+
+/// If `location` is inside of a resource returns the name of that resource.
+fn resource_name(location: &Location) -> Option<&Key> {
+    match location.0.as_slice() {
+        [Key::Name(resources), name, ..] if resources == "resources" => Some(name),
+        _ => None,
+    }
+}
+
+fn is_global_copy(a: &Location, b: &Location) -> bool {
+    match (a.0.as_slice(), b.0.as_slice()) {
+        (
+            [Key::Name(h1), Key::Index(_), rest1 @ ..],
+            [Key::Name(h2), Key::Index(_), rest2 @ ..],
+        ) => {
+            h1 == "hammer"
+                && h2 == "hammer"
+                && rest1 == rest2
+                && matches!(rest1.first(), Some(Key::Name(x)) if x == "headers" || x == "cookies")
+        }
+        _ => a == b,
+    }
+}
+
+/// Finds all cycles in the resource dependency graph, every cycle is reported once starting
+/// from its alphabetically first resource and ending with that resource again.
+fn find_cycles<'a>(
+    dependencies: &BTreeMap<&'a str, Vec<(&'a str, Location)>>,
+) -> Vec<Vec<&'a str>> {
+    fn visit<'a>(
+        node: &'a str,
+        dependencies: &BTreeMap<&'a str, Vec<(&'a str, Location)>>,
+        stack: &mut Vec<&'a str>,
+        found: &mut HashSet<Vec<&'a str>>,
+    ) {
+        if let Some(start) = stack.iter().position(|x| *x == node) {
+            let mut cycle = stack[start..].to_vec();
+            let min = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+            cycle.rotate_left(min);
+            cycle.push(cycle[0]);
+            found.insert(cycle);
+            return;
+        }
+
+        stack.push(node);
+        for (to, _) in dependencies.get(node).into_iter().flatten() {
+            visit(to, dependencies, stack, found);
+        }
+        stack.pop();
+    }
+
+    let mut found = HashSet::new();
+    for node in dependencies.keys() {
+        visit(node, dependencies, &mut vec![], &mut found);
+    }
+
+    let mut cycles = found.into_iter().collect::<Vec<_>>();
+    cycles.sort();
+    cycles
+}
+
+/// Returns the candidate with the smallest edit distance to `name` if it is close enough.
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Option<&'a str> {
+    fn distance(a: &str, b: &str) -> usize {
+        let b = b.chars().collect::<Vec<_>>();
+        let mut row = (0..=b.len()).collect::<Vec<_>>();
+        for (i, ca) in a.chars().enumerate() {
+            let mut previous = row[0];
+            row[0] = i + 1;
+            for j in 0..b.len() {
+                let current = row[j + 1];
+                row[j + 1] = (previous + (ca != b[j]) as usize)
+                    .min(row[j] + 1)
+                    .min(current + 1);
+                previous = current;
+            }
+        }
+        row[b.len()]
+    }
+
+    candidates
+        .map(|c| (distance(name, c), c))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, c)| c.as_str())
+}
+
+fn print_span(path: &Path, text: &str, span: Range<usize>, location: &Location) {
+    let line_start = text[..span.start].rfind('\n').map(|x| x + 1).unwrap_or(0);
+    let line_end = text[span.start..]
+        .find('\n')
+        .map(|x| x + span.start)
+        .unwrap_or(text.len());
+    let line = text[..span.start].matches('\n').count() + 1;
+    let column = text[line_start..span.start].chars().count() + 1;
+    let width = text[span.start..span.end.min(line_end)]
+        .chars()
+        .count()
+        .max(1);
+    let gutter = " ".repeat(line.to_string().len());
+
+    eprintln!(
+        "{gutter}--> {}:{line}:{column} ({location})",
+        path.display()
+    );
+    eprintln!("{gutter} |");
+    eprintln!("{line} | {}", &text[line_start..line_end]);
+    eprintln!(
+        "{gutter} | {}\x1b[31;1m{}\x1b[0m",
+        " ".repeat(column - 1),
+        "^".repeat(width)
+    );
+}
+
+/// A TOML document that remembers where every key is located.
+///
+/// Only keys are spanned because tables that are only defined implicitly, like `feeders` in
+/// `[feeders.users]`, do not have a span.
+enum Node {
+    Table(Vec<(Spanned<String>, Node)>),
+    Array(Vec<Node>),
+    Other,
+}
+
+impl Node {
+    /// Returns the span of the key at `keys`, or if `partial` is set of the closest parent that
+    /// exists.
+    fn find(&self, keys: &[Key], partial: bool) -> Option<Range<usize>> {
+        let (first, rest) = keys.split_first()?;
+        let (span, child) = match (self, first) {
+            (Node::Table(entries), Key::Name(name)) => entries
+                .iter()
+                .find(|(k, _)| k.get_ref() == name)
+                // Header names are stored lowercase
+                .or_else(|| {
+                    entries
+                        .iter()
+                        .find(|(k, _)| k.get_ref().eq_ignore_ascii_case(name))
+                })
+                .map(|(k, v)| (Some(k.span()), v))?,
+            (Node::Array(items), Key::Index(index)) => (None, items.get(*index)?),
+            _ => return None,
+        };
+
+        match rest {
+            [] => span,
+            rest => match child.find(rest, partial) {
+                None if partial => span,
+                found => found,
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Node;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "any value")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut entries = vec![];
+                while let Some(key) = map.next_key::<Spanned<String>>()? {
+                    entries.push((key, map.next_value()?));
+                }
+                Ok(Node::Table(entries))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut items = vec![];
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(Node::Array(items))
+            }
+
+            fn visit_bool<E>(self, _: bool) -> Result<Self::Value, E> {
+                Ok(Node::Other)
+            }
+
+            fn visit_i64<E>(self, _: i64) -> Result<Self::Value, E> {
+                Ok(Node::Other)
+            }
+
+            fn visit_u64<E>(self, _: u64) -> Result<Self::Value, E> {
+                Ok(Node::Other)
+            }
+
+            fn visit_f64<E>(self, _: f64) -> Result<Self::Value, E> {
+                Ok(Node::Other)
+            }
+
+            fn visit_str<E>(self, _: &str) -> Result<Self::Value, E> {
+                Ok(Node::Other)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
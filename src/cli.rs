@@ -3,20 +3,56 @@ use std::path::PathBuf;
 use clap::ValueHint;
 
 #[derive(clap::Parser)]
-#[command(about, version)]
+#[command(
+    about,
+    version,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Specify how many tasks to use for hammering.
-    #[arg(long, short, default_value_t = 1, value_parser = clap::value_parser!(u64).range(0..))]
+    #[arg(
+        long, short, global = true, default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(0..)
+    )]
     pub tasks: u64,
 
     /// Turn on verbose logging
     #[arg(
-        long, short, action = clap::ArgAction::Count,
+        long, short, global = true, action = clap::ArgAction::Count,
         value_parser = clap::value_parser!(u8).range(0..=2)
     )]
     pub verbose: u8,
 
-    /// TOML file with hammering configuration.
+    /// Format of the configuration file.
+    #[arg(long, global = true, value_enum, default_value_t = ConfigFormat::Auto)]
+    pub config_format: ConfigFormat,
+
+    /// Set a variable that can be interpolated into the configuration using '${vars.NAME}'.
+    #[arg(
+        long = "set", global = true, value_name = "NAME=VALUE",
+        value_parser = parse_variable
+    )]
+    pub vars: Vec<(String, String)>,
+
+    #[command(flatten)]
+    pub overrides: Overrides,
+
+    /// Print every request as a curl command instead of hammering.
+    #[arg(long)]
+    pub print_curl: bool,
+
+    /// Redact secrets like the values of resources and authentication headers when printing curl
+    /// commands.
+    #[arg(long, requires = "print_curl")]
+    pub redact: bool,
+
+    /// TOML file with hammering configuration, or '-' to read it from stdin.
+    ///
+    /// The configuration may also be written in YAML or JSON, see '--config-format'.
     ///
     /// # Format
     /// It should contain an array of tables called "hammer" where each table should have the
@@ -25,31 +61,193 @@ pub struct Args {
     ///     'count' a number specifying how many reqeusts to make
     ///
     /// It can also have these optional properties:
+    ///     'query': a query parameter name -> value map, both will be percent encoded
     ///     'method': a string containing the HTTP method to use
     ///     'cookies': a cookie name -> cookie value map
     ///                a cookie value may also be '{}' which unsets that cookie if it was
     ///                previously set in the global cookies table
+    ///     'encode_cookies': set to false if the names and values in 'cookies' are already
+    ///                       URL encoded
     ///     'headers': a header name -> header value map
+    ///     'auth': a table with a 'type' of "basic", "bearer" or "digest" and either a 'user' and
+    ///             a 'password' or a 'token'
+    ///     'sign': a table with a 'type' of "hmac" or "aws_sigv4" that adds a signature to every
+    ///             request
     ///     'body': a string used as the body for the request
+    ///     'body_json': a table that will be sent as JSON instead of 'body', strings inside of it
+    ///                  are interpolated
     ///     'name': a string displayed while hammering instead of the default `${METHOD} ${URI}` name
     ///     'max_concurrency': a number representing the maximum number of tasks that should be used
     ///                        to hammer the url
+    ///     'tags': a list of strings that can be used to select entries with '--tags'
+    ///     'extends': the name of a template from the top level 'templates' table to inherit
+    ///                properties from
     ///
     /// Also optionally, a 'cookies' table may be specified at the top level which will be
     /// propagated to all other entries in the file.
+    /// 'cookie_jar' may be set to "global" or "task" to send cookies set by responses with the
+    /// following requests.
     ///
     /// # Example entry
     /// [[hammer]]
     /// name = "my endpoint"
     /// uri = "http://127.0.0.1:8000/do_something"
     /// method = "POST"
-    /// cookie = { "some-cookie" = "value" }
+    /// cookies = { "some-cookie" = "value" }
     /// headers = { "Content-Type" = "application/json" }
     /// body = '''
     ///   { "do":"thing" }
     /// '''
     /// count = 20000
     /// max_concurrency = 10
-    #[arg(verbatim_doc_comment, value_hint = ValueHint::FilePath)]
-    pub config: PathBuf,
+    #[arg(verbatim_doc_comment, value_hint = ValueHint::FilePath, required = true)]
+    pub config: Option<PathBuf>,
+}
+
+/// Options that change which entries are hammered and how.
+#[derive(clap::Args)]
+pub struct Overrides {
+    /// Only hammer entries whose name matches this glob, may be given multiple times.
+    #[arg(long, value_name = "GLOB")]
+    pub only: Vec<String>,
+
+    /// Skip entries whose name matches this glob, may be given multiple times.
+    #[arg(long, value_name = "GLOB")]
+    pub skip: Vec<String>,
+
+    /// Only hammer entries that have at least one of these comma separated tags.
+    #[arg(long, value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    /// Send this many requests for every entry instead of the count from the configuration.
+    #[arg(long, value_name = "COUNT", conflicts_with = "count_scale")]
+    pub count_override: Option<u64>,
+
+    /// Multiply the count of every entry by this factor, for example 0.1 for a smoke test.
+    #[arg(long, value_name = "FACTOR", value_parser = parse_factor)]
+    pub count_scale: Option<f64>,
+
+    /// Send every request to this server instead, replacing the scheme and authority of every uri.
+    #[arg(long, value_name = "URL", value_hint = ValueHint::Url)]
+    pub target_base_url: Option<String>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Check a configuration file for mistakes without sending any requests.
+    Check {
+        /// The configuration file to check.
+        #[arg(value_hint = ValueHint::FilePath)]
+        config: PathBuf,
+    },
+    /// Replay requests recorded in an access log.
+    Replay(ReplayArgs),
+    /// Generate a hammering configuration from another format.
+    #[command(subcommand)]
+    Import(ImportCommand),
+    /// Generate a hammering configuration from a curl command line.
+    FromCurl {
+        /// The curl command, either as a single string or as separate arguments.
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+
+        #[command(flatten)]
+        options: ImportOptions,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub enum ImportCommand {
+    /// Import requests recorded in a HAR file.
+    Har {
+        /// The HAR file to import.
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+
+        #[command(flatten)]
+        options: ImportOptions,
+    },
+    /// Generate an entry for every operation in an OpenAPI 3 specification.
+    #[command(name = "openapi")]
+    OpenApi {
+        /// The specification to import, in either YAML or JSON.
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+
+        /// Base URL of the server to use instead of the first one in the specification.
+        #[arg(long, value_hint = ValueHint::Url)]
+        server: Option<String>,
+
+        #[command(flatten)]
+        options: ImportOptions,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct ImportOptions {
+    /// The count every generated entry will have.
+    #[arg(long, short, default_value_t = 1)]
+    pub count: u64,
+
+    /// Write the configuration to this file instead of stdout.
+    #[arg(long, short, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    /// Guess the format from the file extension, falling back to TOML.
+    Auto,
+    Toml,
+    Yaml,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Guess the format from the file extension.
+    Auto,
+    /// The nginx/Apache combined log format.
+    Combined,
+    /// One JSON object per line with 'method', 'path', 'headers', 'body' and 'timestamp' fields.
+    Jsonl,
+}
+
+#[derive(clap::Args)]
+pub struct ReplayArgs {
+    /// The access log to replay.
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub log: PathBuf,
+
+    /// Scheme and authority the recorded requests will be sent to.
+    #[arg(long, value_hint = ValueHint::Url)]
+    pub target: String,
+
+    /// Format of the access log.
+    #[arg(long, value_enum, default_value_t = LogFormat::Auto)]
+    pub format: LogFormat,
+
+    /// Replay requests with their original timing sped up by this factor.
+    ///
+    /// A factor of 1 replays the log in real time, 2 twice as fast and so on. If not specified the
+    /// requests will be sent as fast as possible. Every request is sent at its own time, so this
+    /// cannot be combined with '--tasks'.
+    #[arg(long, value_parser = parse_factor, conflicts_with = "tasks")]
+    pub speed: Option<f64>,
+}
+
+fn parse_factor(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(factor),
+        Ok(_) => Err("must be a positive number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_variable(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some(("", _)) => Err("variable name must not be empty".to_string()),
+        Some((name, value)) => Ok((name.to_string(), value.to_string())),
+        None => Err("expected NAME=VALUE".to_string()),
+    }
 }
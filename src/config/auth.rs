@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use hyper::{
+    client::connect::Connect,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    http::HeaderValue,
+    HeaderMap, Method, Uri,
+};
+use md5::Md5;
+use rand::Rng;
+use serde::{
+    de::{Error, MapAccess},
+    Deserialize,
+};
+use sha2::{Digest, Sha256};
+
+use super::{
+    eval::{Evaluator, FromResponseBody, MaybeDeleted, Scope, Value},
+    FieldName, RequestInfo,
+};
+
+/// The `auth` table of a request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Auth {
+    Basic {
+        user: Value,
+        password: Value,
+    },
+    Bearer {
+        token: Value,
+    },
+    /// Answers the challenge of a server, which is only known after the first response.
+    Digest {
+        user: Value,
+        password: Value,
+    },
+}
+
+/// The user and password of a request that uses digest authentication.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// What authenticates a request once its `auth` table has been evaluated.
+pub enum Authorization {
+    /// The value of the `Authorization` header.
+    Header(HeaderValue),
+    /// The header can only be computed when sending the request.
+    Digest(Credentials),
+}
+
+impl Auth {
+    pub async fn evaluate<C>(
+        self,
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+    ) -> Result<Authorization>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        Ok(match self {
+            Auth::Basic { user, password } => {
+                let user = user.evaluate(evaluator.clone(), scope).await?;
+                let password = password.evaluate(evaluator, scope).await?;
+                let credentials =
+                    base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+                Authorization::Header(
+                    HeaderValue::try_from(format!("Basic {credentials}"))
+                        .context("Basic credentials are not a valid header value")?,
+                )
+            }
+            Auth::Bearer { token } => {
+                let token = token.evaluate(evaluator, scope).await?;
+                Authorization::Header(
+                    HeaderValue::try_from(format!("Bearer {token}"))
+                        .context("Bearer token is not a valid header value")?,
+                )
+            }
+            Auth::Digest { user, password } => Authorization::Digest(Credentials {
+                user: user.evaluate(evaluator.clone(), scope).await?,
+                password: password.evaluate(evaluator, scope).await?,
+            }),
+        })
+    }
+}
+
+/// The latest digest challenge of every server, shared by all tasks.
+#[derive(Debug, Default)]
+pub struct DigestChallenges(Mutex<HashMap<String, Challenge>>);
+
+#[derive(Debug)]
+struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: String,
+    /// Whether `qop=auth` is used, which is the only quality of protection that is supported.
+    qop: bool,
+    /// How many requests have been sent with `nonce` so far.
+    count: u32,
+}
+
+impl DigestChallenges {
+    /// Stores the challenge in a 401 response to a request for `uri`, returns whether one was
+    /// found.
+    pub fn store(&self, uri: &Uri, headers: &HeaderMap) -> Result<bool> {
+        let Some(challenge) = headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(Challenge::parse)
+            .transpose()?
+        else {
+            return Ok(false);
+        };
+
+        let authority = uri.authority().map(|x| x.to_string()).unwrap_or_default();
+        self.0.lock().unwrap().insert(authority, challenge);
+        Ok(true)
+    }
+
+    /// Adds an `Authorization` header answering the challenge of the server if one was received
+    /// already.
+    pub fn authorize(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        credentials: &Credentials,
+        headers: &mut HeaderMap,
+    ) -> Result<()> {
+        let authority = uri.authority().map(|x| x.to_string()).unwrap_or_default();
+        let mut challenges = self.0.lock().unwrap();
+        let Some(challenge) = challenges.get_mut(&authority) else {
+            return Ok(());
+        };
+
+        challenge.count += 1;
+        let path = uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+        let value = challenge.answer(method, path, credentials);
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::try_from(value)
+                .context("Digest credentials are not a valid header value")?,
+        );
+        Ok(())
+    }
+}
+
+impl Challenge {
+    /// Parses a `WWW-Authenticate` header, returns `None` if it is not a digest challenge.
+    fn parse(header: &str) -> Option<Result<Self>> {
+        let (scheme, params) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+        Some(Self::from_params(parse_params(params)))
+    }
+
+    fn from_params(params: HashMap<String, String>) -> Result<Self> {
+        let algorithm = params
+            .get("algorithm")
+            .cloned()
+            .unwrap_or_else(|| "MD5".to_string());
+        if !["MD5", "MD5-SESS", "SHA-256", "SHA-256-SESS"]
+            .contains(&algorithm.to_ascii_uppercase().as_str())
+        {
+            bail!("Digest algorithm {algorithm} is not supported");
+        }
+        let qop = match params.get("qop") {
+            None => false,
+            Some(qop) if qop.split(',').any(|x| x.trim() == "auth") => true,
+            Some(qop) => bail!("Digest quality of protection {qop} is not supported"),
+        };
+
+        Ok(Self {
+            realm: params
+                .get("realm")
+                .cloned()
+                .context("Digest challenge does not have a realm")?,
+            nonce: params
+                .get("nonce")
+                .cloned()
+                .context("Digest challenge does not have a nonce")?,
+            opaque: params.get("opaque").cloned(),
+            algorithm,
+            qop,
+            count: 0,
+        })
+    }
+
+    /// Computes the value of the `Authorization` header as described in RFC 7616.
+    fn answer(&self, method: &Method, path: &str, credentials: &Credentials) -> String {
+        let algorithm = self.algorithm.to_ascii_uppercase();
+        let hash = |data: String| -> String {
+            let digest = match algorithm.starts_with("SHA-256") {
+                true => Sha256::digest(data).to_vec(),
+                false => Md5::digest(data).to_vec(),
+            };
+            digest.iter().map(|byte| format!("{byte:02x}")).collect()
+        };
+
+        let cnonce = format!("{:016x}", rand::thread_rng().gen::<u64>());
+        let nc = format!("{:08x}", self.count);
+        let Credentials { user, password } = credentials;
+
+        let mut ha1 = hash(format!("{user}:{}:{password}", self.realm));
+        if algorithm.ends_with("-SESS") {
+            ha1 = hash(format!("{ha1}:{}:{cnonce}", self.nonce));
+        }
+        let ha2 = hash(format!("{method}:{path}"));
+        let response = match self.qop {
+            true => hash(format!("{ha1}:{}:{nc}:{cnonce}:auth:{ha2}", self.nonce)),
+            false => hash(format!("{ha1}:{}:{ha2}", self.nonce)),
+        };
+
+        let mut value = format!(
+            r#"Digest username="{user}", realm="{}", nonce="{}", uri="{path}", algorithm={}, response="{response}""#,
+            self.realm, self.nonce, self.algorithm
+        );
+        if self.qop {
+            value.push_str(&format!(r#", qop=auth, nc={nc}, cnonce="{cnonce}""#));
+        }
+        if let Some(ref opaque) = self.opaque {
+            value.push_str(&format!(r#", opaque="{opaque}""#));
+        }
+        value
+    }
+}
+
+/// Parses the comma separated `name=value` parameters of a challenge, values may be quoted.
+fn parse_params(text: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = text.trim();
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let after = after.trim_start();
+
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+
+        params.insert(name, value);
+        rest = remaining.trim_start().trim_start_matches(',');
+    }
+    params
+}
+
+/// A resource that requests an access token using the OAuth 2.0 client credentials grant.
+#[derive(Debug, Clone)]
+pub struct OAuth2ClientCredentials {
+    pub token_url: Value,
+    pub client_id: Value,
+    pub client_secret: Value,
+    /// The space separated scopes to request.
+    pub scopes: Option<Value>,
+}
+
+/// The shortest time an access token is used for, regardless of its `expires_in`.
+const MIN_EXPIRES_IN: Duration = Duration::from_secs(1);
+
+impl OAuth2ClientCredentials {
+    pub const FIELDS: &'static [&'static str] =
+        &["token_url", "client_id", "client_secret", "scopes"];
+
+    /// Deserializes the rest of a resource table, keys that are in `fields` but not in
+    /// [`OAuth2ClientCredentials::FIELDS`] are passed to `other`.
+    pub fn from_map<'de, A>(
+        map: &mut A,
+        fields: &'static [&'static [&'static str]],
+        mut other: impl FnMut(&str, &mut A) -> Result<(), A::Error>,
+    ) -> Result<Self, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut token_url = None;
+        let mut client_id = None;
+        let mut client_secret = None;
+        let mut scopes = None;
+
+        while let Some(key) = map.next_key_seed(FieldName(fields))? {
+            match key.as_str() {
+                "token_url" => token_url = Some(map.next_value()?),
+                "client_id" => client_id = Some(map.next_value()?),
+                "client_secret" => client_secret = Some(map.next_value()?),
+                "scopes" => scopes = Some(map.next_value()?),
+                key => other(key, map)?,
+            }
+        }
+
+        Ok(Self {
+            token_url: token_url.ok_or_else(|| A::Error::missing_field("token_url"))?,
+            client_id: client_id.ok_or_else(|| A::Error::missing_field("client_id"))?,
+            client_secret: client_secret.ok_or_else(|| A::Error::missing_field("client_secret"))?,
+            scopes,
+        })
+    }
+
+    /// Requests a new access token, returns it along with how long it is valid if the server
+    /// said so.
+    pub async fn fetch<C>(
+        self,
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+    ) -> Result<(String, Option<Duration>)>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let mut form = vec![
+            ("grant_type", "client_credentials".to_string()),
+            (
+                "client_id",
+                self.client_id.evaluate(evaluator.clone(), scope).await?,
+            ),
+            (
+                "client_secret",
+                self.client_secret
+                    .evaluate(evaluator.clone(), scope)
+                    .await?,
+            ),
+        ];
+        if let Some(scopes) = self.scopes {
+            form.push(("scope", scopes.evaluate(evaluator.clone(), scope).await?));
+        }
+        let body = form
+            .iter()
+            .map(|(name, value)| format!("{name}={}", urlencoding::encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let mut headers = HeaderMap::<MaybeDeleted>::default();
+        headers.insert(
+            CONTENT_TYPE,
+            MaybeDeleted::Value(Value::Constant(
+                "application/x-www-form-urlencoded".to_string(),
+            )),
+        );
+        headers.insert(
+            ACCEPT,
+            MaybeDeleted::Value(Value::Constant("application/json".to_string())),
+        );
+        let request = RequestInfo {
+            uri: self.token_url,
+            method: Method::POST,
+            query: Default::default(),
+            cookies: Default::default(),
+            headers,
+            encode_cookies: true,
+            auth: None,
+            sign: None,
+            body: Box::new(Value::Constant(body)),
+            body_json: None,
+        };
+
+        let response = FromResponseBody::response(request, evaluator, scope, false).await?;
+        if !response.status.is_success() {
+            bail!(
+                "Token request failed with status {}: {}",
+                response.status,
+                response.body
+            );
+        }
+
+        #[derive(Deserialize)]
+        struct Token {
+            access_token: String,
+            expires_in: Option<i64>,
+        }
+        let token = serde_json::from_str::<Token>(&response.body)
+            .context("Token response does not contain an access_token")?;
+        // A token that expires right away would be requested again continuously
+        let expires_in = token
+            .expires_in
+            .filter(|&seconds| seconds > 0)
+            .map(|seconds| Duration::from_secs(seconds as u64).max(MIN_EXPIRES_IN));
+        Ok((token.access_token, expires_in))
+    }
+}
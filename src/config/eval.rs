@@ -1,51 +1,480 @@
-use std::{borrow::Cow, collections::HashMap, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex as SyncMutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::{anyhow, bail, Context, Result};
 use async_recursion::async_recursion;
-use hyper::client::connect::Connect;
-use serde::Deserialize;
+use hyper::{client::connect::Connect, StatusCode, Uri};
+use rand::seq::SliceRandom;
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize,
+};
 use tokio::sync::Mutex;
 
+use crate::cookie::CookieJars;
+
 use super::{
-    format::{format_callback, format_one},
-    AlmostRequest, RequestInfo,
+    auth::DigestChallenges,
+    extract::{Extract, Response},
+    feeder::{Feeder, Row},
+    format::{self, format_callback, format_one, Expression},
+    resource::{Pool, Resource, ResourceScope},
+    walk::{Field, Location},
+    AlmostRequest, FirstFieldName, RequestInfo, TOML_DATETIME,
 };
 
+/// The name of a resource and the task it belongs to if it is task scoped.
+type ResourceKey = (String, Option<usize>);
+
 pub struct Evaluator<C: Connect + Clone + Send + Sync + 'static> {
     pub client: hyper::Client<C>,
     pub verbose: u8,
-    pub resources: HashMap<String, Mutex<Value>>,
-    pub request_cache: Mutex<HashMap<AlmostRequest, String>>,
+    pub resources: HashMap<String, Resource>,
+    /// The values of resources, resources with `scope = "task"` have a separate one for each task.
+    pub resource_states: SyncMutex<HashMap<ResourceKey, Arc<Mutex<ResourceState>>>>,
+    pub feeders: HashMap<String, Feeder>,
+    pub vars: HashMap<String, String>,
+    /// Replaces the scheme and authority of every request if set.
+    pub target: Option<Uri>,
+    pub request_cache: Mutex<HashMap<AlmostRequest, Arc<Response>>>,
+    /// Stores the cookies set by responses if `cookie_jar` is set in the configuration.
+    pub cookie_jar: Option<CookieJars>,
+    pub digest_challenges: DigestChallenges,
+}
+
+impl<C: Connect + Clone + Send + Sync + 'static> Evaluator<C> {
+    /// Sends a request for `task` and stores the cookies set by the response in the cookie jar.
+    ///
+    /// Requests using digest authentication are sent again if the server responds with a new
+    /// challenge.
+    pub async fn send(
+        &self,
+        task: usize,
+        request: AlmostRequest,
+    ) -> Result<hyper::Response<hyper::Body>> {
+        let retry = request.digest.is_some().then(|| request.clone());
+        let uri = request.uri().clone();
+        let response = self.send_once(task, request).await?;
+
+        match retry {
+            Some(retry)
+                if response.status() == StatusCode::UNAUTHORIZED
+                    && self.digest_challenges.store(&uri, response.headers())? =>
+            {
+                hyper::body::to_bytes(response.into_body()).await?;
+                self.send_once(task, retry).await
+            }
+            _ => Ok(response),
+        }
+    }
+
+    async fn send_once(
+        &self,
+        task: usize,
+        mut request: AlmostRequest,
+    ) -> Result<hyper::Response<hyper::Body>> {
+        let uri = request.uri.clone();
+        if let Some(ref credentials) = request.digest {
+            self.digest_challenges.authorize(
+                &request.method,
+                &uri,
+                credentials,
+                &mut request.headers,
+            )?;
+        }
+        if let Some(ref signer) = request.signer {
+            signer
+                .sign(
+                    &request.method,
+                    &uri,
+                    &mut request.headers,
+                    &request.body,
+                    SystemTime::now(),
+                )
+                .context("Failed to sign request")?;
+        }
+
+        let response = self.client.request(request.into()).await?;
+        if let Some(ref jar) = self.cookie_jar {
+            jar.store(task, &uri, response.headers());
+        }
+        Ok(response)
+    }
+
+    /// Returns the values of all resources that have been evaluated so far.
+    pub async fn resolved_resources(&self) -> Vec<String> {
+        let mut values = vec![];
+        let states = self
+            .resource_states
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for state in states {
+            if let Some(ref resolved) = state.lock().await.resolved {
+                values.extend(resolved.values.iter().cloned());
+            }
+        }
+        values
+    }
+
+    /// Invalidates the resources used in `scope` that should be refreshed after a response with
+    /// `status`, returns whether the request should be retried.
+    pub async fn refresh_on(&self, scope: &Scope, status: StatusCode) -> bool {
+        let used = scope.dynamic.lock().unwrap().clone();
+        let mut retry = false;
+        for (name, generation) in used {
+            let state = self.state(&name, scope.task).unwrap();
+            let mut state = state.lock().await;
+            if state.resource.refresh_on.contains(&status.as_u16()) {
+                // Other tasks may have already refreshed the value after getting the same status
+                if state.generation == generation {
+                    if self.verbose > 0 {
+                        eprintln!("Refreshing resource {name} after {status}");
+                    }
+                    state.invalidate();
+                }
+                retry = true;
+            }
+        }
+        retry
+    }
+
+    /// Returns the state of a resource as seen by `task`.
+    fn state(&self, name: &str, task: usize) -> Option<Arc<Mutex<ResourceState>>> {
+        let resource = self.resources.get(name)?;
+        let task = match resource.scope {
+            ResourceScope::Global => None,
+            ResourceScope::Task | ResourceScope::Request => Some(task),
+        };
+        let mut states = self.resource_states.lock().unwrap();
+        let state = states
+            .entry((name.to_string(), task))
+            .or_insert_with(|| Arc::new(Mutex::new(ResourceState::new(resource.clone()))));
+        Some(state.clone())
+    }
+
+    /// Evaluates a resource again whenever its ttl runs out, as long as the value has been used
+    /// since it was last evaluated.
+    async fn refresh_in_background(self: Arc<Self>, name: String, task: usize) {
+        let state = self.state(&name, task).unwrap();
+        loop {
+            let (resource, generation, expires) = {
+                let state = state.lock().await;
+                match state.resolved {
+                    // Refreshing a bit early keeps requests from waiting for the new value
+                    Some(Resolved {
+                        at, ttl: Some(ttl), ..
+                    }) => (
+                        state.resource.clone(),
+                        state.generation,
+                        at + ttl.mul_f64(0.9),
+                    ),
+                    _ => break,
+                }
+            };
+
+            tokio::time::sleep_until(expires.into()).await;
+            // Once nothing uses the resource anymore, the next use evaluates it again instead
+            if !state.lock().await.used {
+                break;
+            }
+            if self.verbose > 0 {
+                eprintln!("Refreshing resource {name}");
+            }
+
+            // The old value stays in use while the new one is being evaluated
+            match resource.evaluate(self.clone(), &Scope::new(task)).await {
+                Ok((values, ttl)) => {
+                    let mut state = state.lock().await;
+                    if state.generation == generation {
+                        state.set(Arc::new(values), ttl);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("\x1b[2KFailed to refresh resource {name}: {e:#}");
+                    break;
+                }
+            }
+        }
+        // It will be restarted once the resource is evaluated again
+        state.lock().await.refreshing = false;
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Hash, PartialEq, Eq)]
-#[serde(tag = "format")]
-enum BodyExtract {
-    #[serde(rename = "json")]
-    Json { pointer: String },
+/// A resource along with the values it evaluated to.
+pub struct ResourceState {
+    resource: Resource,
+    resolved: Option<Resolved>,
+    /// Changes every time the value does.
+    generation: u64,
+    /// Whether the value is being refreshed in the background.
+    refreshing: bool,
+    /// Whether the value has been used since it was evaluated.
+    used: bool,
+    /// The index of the next value of a round robin pool.
+    next: usize,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// The values of a resource and when they were evaluated.
+struct Resolved {
+    /// All values of a pool, or just the one value of other resources.
+    values: Arc<Vec<String>>,
+    at: Instant,
+    /// How long the values can be used.
+    ttl: Option<Duration>,
+}
+
+impl ResourceState {
+    pub fn new(resource: Resource) -> Self {
+        Self {
+            resource,
+            resolved: None,
+            generation: 0,
+            refreshing: false,
+            used: false,
+            next: 0,
+        }
+    }
+
+    /// The evaluated values if they have not expired yet.
+    fn current(&self) -> Option<Arc<Vec<String>>> {
+        let resolved = self.resolved.as_ref()?;
+        match resolved.ttl {
+            Some(ttl) if resolved.at.elapsed() >= ttl => None,
+            _ => Some(resolved.values.clone()),
+        }
+    }
+
+    fn set(&mut self, values: Arc<Vec<String>>, ttl: Option<Duration>) {
+        self.resolved = Some(Resolved {
+            values,
+            at: Instant::now(),
+            ttl,
+        });
+        self.generation += 1;
+        self.used = false;
+    }
+
+    /// Picks the value to use from the evaluated ones.
+    fn pick(&mut self, values: &[String]) -> Option<String> {
+        match self.resource.pool {
+            None => values.first(),
+            Some(Pool::RoundRobin) => {
+                let value = values.get(self.next % values.len().max(1));
+                self.next = self.next.wrapping_add(1);
+                value
+            }
+            Some(Pool::Random) => values.choose(&mut rand::thread_rng()),
+        }
+        .cloned()
+    }
+
+    fn invalidate(&mut self) {
+        self.resolved = None;
+        self.generation += 1;
+    }
+}
+
+/// State local to the evaluation of a single request.
+///
+/// Values drawn from feeders and pools are remembered here so that all references to the same
+/// feeder or pool in one request see the same row or value.
+pub struct Scope {
+    pub task: usize,
+    rows: SyncMutex<HashMap<String, Arc<Row>>>,
+    picked: SyncMutex<HashMap<String, String>>,
+    resolving: SyncMutex<Vec<String>>,
+    /// Resources whose value may differ between requests and the generation of the value that
+    /// was used.
+    dynamic: SyncMutex<Vec<(String, u64)>>,
+    /// How often a feeder has been read, including reads of rows that were already drawn.
+    feeder_reads: AtomicUsize,
+}
+
+impl Scope {
+    pub fn new(task: usize) -> Self {
+        Self {
+            task,
+            rows: Default::default(),
+            picked: Default::default(),
+            resolving: Default::default(),
+            dynamic: Default::default(),
+            feeder_reads: Default::default(),
+        }
+    }
+
+    /// Whether anything evaluated in this scope may evaluate differently next time.
+    pub fn is_dynamic(&self) -> bool {
+        !self.rows.lock().unwrap().is_empty() || !self.dynamic.lock().unwrap().is_empty()
+    }
+
+    fn row(&self, name: &str, feeder: &Feeder) -> Result<Arc<Row>> {
+        self.feeder_reads.fetch_add(1, Ordering::Relaxed);
+        let mut rows = self.rows.lock().unwrap();
+        Ok(match rows.get(name) {
+            Some(row) => row.clone(),
+            None => {
+                let row = feeder.next(self.task)?;
+                rows.insert(name.to_string(), row.clone());
+                row
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct FromResponseBody {
-    #[serde(flatten)]
     request: RequestInfo,
-    extract: Option<BodyExtract>,
+    extract: Option<Extract>,
     format: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(untagged)]
+impl FromResponseBody {
+    /// Keys of a request table that are not part of the request itself.
+    pub const EXTRA_FIELDS: &'static [&'static str] = &["extract", "format"];
+    const FIELDS: &'static [&'static [&'static str]] = &[RequestInfo::FIELDS, Self::EXTRA_FIELDS];
+
+    /// Deserializes a request table, `first` is a key that has already been read from `map`.
+    ///
+    /// Keys that are in `fields` but not in [`FromResponseBody::FIELDS`] are passed to `other`.
+    pub fn from_map<'de, A>(
+        map: A,
+        first: Option<String>,
+        fields: &'static [&'static [&'static str]],
+        mut other: impl FnMut(&str, &mut A) -> Result<(), A::Error>,
+    ) -> Result<Self, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut extract = None;
+        let mut format = None;
+
+        let request = RequestInfo::from_map(map, first, fields, |key, map| {
+            match key {
+                "extract" => extract = Some(map.next_value()?),
+                "format" => format = Some(map.next_value()?),
+                key => other(key, map)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(Self {
+            request,
+            extract,
+            format,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Value {
     Formatted(String),
-    // FIXME: Implement deserialization for constant values
     Constant(String),
-    Request(FromResponseBody),
+    Request(Box<FromResponseBody>),
+}
+
+pub(super) struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a string, a number, a boolean or a request table")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Value::Formatted(v.to_string()))
+    }
+
+    fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Value::Constant(v.to_string()))
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Value::Constant(v.to_string()))
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(Value::Constant(v.to_string()))
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        // Unlike `to_string`, this keeps the fractional part of `1.0`
+        Ok(Value::Constant(format!("{v:?}")))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let first = map.next_key_seed(FirstFieldName(FromResponseBody::FIELDS))?;
+        if first.as_deref() == Some(TOML_DATETIME) {
+            return Ok(Value::Constant(map.next_value()?));
+        }
+
+        Ok(Value::Request(Box::new(FromResponseBody::from_map(
+            map,
+            first,
+            FromResponseBody::FIELDS,
+            |_, _| unreachable!("only request fields are allowed"),
+        )?)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
 }
 
 impl Value {
     pub fn empty() -> Self {
         Self::Constant(String::new())
     }
+
+    pub fn walk<'a>(&'a self, location: Location, f: &mut impl FnMut(Location, Field<'a>)) {
+        self.walk_with(location, f, Field::Formatted)
+    }
+
+    /// Like [`Value::walk`] but uses `kind` for the field if this value is a string.
+    pub fn walk_with<'a>(
+        &'a self,
+        location: Location,
+        f: &mut impl FnMut(Location, Field<'a>),
+        kind: fn(&'a str) -> Field<'a>,
+    ) {
+        match self {
+            Value::Formatted(fmtstr) => f(location, kind(fmtstr)),
+            Value::Constant(value) => f(location, Field::Constant(value, kind)),
+            Value::Request(req) => {
+                req.request.walk(location.clone(), f);
+                if let Some(ref format) = req.format {
+                    f(location.join("format"), Field::ResponseFormat(format));
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Formatted(value) | Value::Constant(value) => write!(f, "{value}"),
+            Value::Request(req) => write!(f, "<{} {}>", req.request.method, req.request.uri),
+        }
+    }
 }
 
 impl From<String> for Value {
@@ -60,204 +489,501 @@ impl From<String> for Value {
 }
 
 impl FromResponseBody {
+    /// Executes the request, responses to identical requests are reused if `cached` is set.
     #[async_recursion]
-    pub async fn resolve<C: Connect + Clone + Send + Sync + 'static>(
-        self,
+    pub(super) async fn response<C>(
+        request: RequestInfo,
         evaluator: Arc<Evaluator<C>>,
-    ) -> Result<String> {
+        scope: &Scope,
+        cached: bool,
+    ) -> Result<Arc<Response>>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
         // FIXME: entry().or_insert_with_key(|| {}) cannot be used here because we need to use
         //        await in the insert callback
-        let request = self.request.build(evaluator.clone()).await?;
-        let mut cache = evaluator.request_cache.lock().await;
-        let body: &str = match cache.get(&request) {
-            Some(string) => string,
+        let request = request.build(evaluator.clone(), scope).await?;
+        let cache = evaluator.request_cache.lock().await;
+        let response = match cache.get(&request).filter(|_| cached) {
+            Some(response) => response.clone(),
             None => {
                 if evaluator.verbose > 0 {
                     eprintln!("Executing {} {}", request.method(), request.uri());
                 }
 
                 drop(cache);
-                let response = evaluator.client.request(request.clone().into()).await?;
+                let (parts, body) = evaluator
+                    .send(scope.task, request.clone())
+                    .await?
+                    .into_parts();
+                let response = Arc::new(Response {
+                    status: parts.status,
+                    headers: parts.headers,
+                    body: String::from_utf8(hyper::body::to_bytes(body).await?.to_vec())?,
+                });
 
-                cache = evaluator.request_cache.lock().await;
                 // FIXME: This could be a try_insert instead.
-                cache.insert(
-                    request.clone(),
-                    String::from_utf8(hyper::body::to_bytes(response.into_body()).await?.to_vec())?,
-                );
-                cache.get(&request).unwrap()
+                let mut cache = evaluator.request_cache.lock().await;
+                cache.insert(request, response.clone());
+                response
             }
         };
 
         if evaluator.verbose > 1 {
-            eprintln!("Response: '{}'", body);
+            eprintln!("Response: '{}'", response.body);
         }
 
+        Ok(response)
+    }
+
+    /// Executes the request and extracts the value from the response, responses to identical
+    /// requests are reused if `cached` is set.
+    pub async fn resolve<C>(
+        self,
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+        cached: bool,
+    ) -> Result<String>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let response = Self::response(self.request, evaluator.clone(), scope, cached).await?;
+
         let extracted = match self.extract {
-            Some(BodyExtract::Json { pointer }) => {
-                let value = serde_json::from_str::<serde_json::Value>(body)
-                    .context("Failed to deserialize response")?;
-
-                let val = value
-                    .pointer(&pointer)
-                    .context("Response does not contain expected value")?;
-
-                Cow::Owned(if val.is_string() {
-                    val.as_str().unwrap().to_string()
-                } else {
-                    val.to_string()
-                })
-            }
-            None => Cow::Borrowed(body),
+            Some(extract) => extract.extract(&response)?,
+            None => Cow::Borrowed(response.body.as_str()),
         };
 
-        let formatted = {
-            if let Some(fmtstr) = self.format {
-                Cow::Owned(format_one(fmtstr, &extracted)?)
-            } else {
-                extracted
-            }
+        match self.format {
+            Some(fmtstr) => format_response(evaluator, scope, fmtstr, &response, &extracted).await,
+            None => Ok(extracted.into_owned()),
+        }
+    }
+
+    /// Like [`FromResponseBody::resolve`] but extracts all values of a pool.
+    pub async fn resolve_list<C>(
+        self,
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+        cached: bool,
+    ) -> Result<Vec<String>>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let response = Self::response(self.request, evaluator.clone(), scope, cached).await?;
+
+        let values = match self.extract {
+            Some(extract) => extract.extract_list(&response)?,
+            None => vec![response.body.clone()],
+        };
+
+        let Some(fmtstr) = self.format else {
+            return Ok(values);
         };
+        let mut formatted = Vec::with_capacity(values.len());
+        for value in values.iter() {
+            formatted.push(
+                format_response(evaluator.clone(), scope, fmtstr.clone(), &response, value).await?,
+            );
+        }
+        Ok(formatted)
+    }
+}
 
-        Ok(formatted.to_string())
+/// Formats an extracted value using the `format` of a response, which either contains a single
+/// `{}` or `${...}` format specifiers that may also refer to the response.
+async fn format_response<C>(
+    evaluator: Arc<Evaluator<C>>,
+    scope: &Scope,
+    fmtstr: String,
+    response: &Response,
+    value: &str,
+) -> Result<String>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    if format::is_template(&fmtstr) {
+        let formatting = Formatting::Response { response, value };
+        format_with_resources(evaluator, scope, &fmtstr, Some(formatting)).await
+    } else {
+        format_one(fmtstr, value)
     }
 }
 
 impl Value {
-    pub async fn evaluate<C>(self, evaluator: Arc<Evaluator<C>>) -> Result<String>
+    pub async fn evaluate<C>(self, evaluator: Arc<Evaluator<C>>, scope: &Scope) -> Result<String>
     where
         C: Connect + Clone + Send + Sync + 'static,
     {
         Ok(match self {
             Self::Constant(cnst) => cnst,
-            Self::Formatted(fmtstr) => format_with_resources(evaluator.clone(), &fmtstr).await?,
-            Self::Request(req) => req.resolve(evaluator.clone()).await?,
+            Self::Formatted(fmtstr) => {
+                format_with_resources(evaluator, scope, &fmtstr, None).await?
+            }
+            Self::Request(req) => req.resolve(evaluator, scope, true).await?,
         })
     }
 
-    pub async fn evaluate_ref<C>(&mut self, evaluator: Arc<Evaluator<C>>) -> Result<String>
+    /// Like [`Value::evaluate`] but `${body}` is replaced by `body`, the body of the request that
+    /// this value is a part of.
+    pub async fn evaluate_with_body<C>(
+        self,
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+        body: &str,
+    ) -> Result<String>
     where
         C: Connect + Clone + Send + Sync + 'static,
     {
-        Ok(match *self {
-            Value::Constant(ref cnst) => cnst.clone(),
-            Value::Formatted(ref fmtstr) => {
-                let resolved = format_with_resources(evaluator, fmtstr.as_str()).await?;
-                *self = Value::Constant(resolved.clone());
-                resolved
-            }
-            ref mut value @ Value::Request(_) => {
-                // FIXME: This enum dance theoretically could be avoided but
-                //        requires uninitialized memory - aka. UB
-                let req = match std::mem::replace(value, Value::Constant(String::new())) {
-                    Value::Request(req) => req,
-                    _ => unreachable!(),
-                };
-                let resolved = req.resolve(evaluator).await?;
-
-                match *value {
-                    Value::Constant(ref mut cnst) => {
-                        cnst.clone_from(&resolved);
-                    }
-                    _ => unreachable!(),
-                }
-
-                resolved
+        match self {
+            Self::Formatted(fmtstr) => {
+                let formatting = Formatting::Request { body };
+                format_with_resources(evaluator, scope, &fmtstr, Some(formatting)).await
             }
-        })
+            value => value.evaluate(evaluator, scope).await,
+        }
     }
 
     pub async fn resolve_resource<C>(
         evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
         resource: &str,
     ) -> Result<Option<String>>
     where
         C: Connect + Clone + Send + Sync + 'static,
     {
-        Ok(match evaluator.clone().resources.get(resource) {
-            Some(rv) => {
-                if let Ok(mut vlock) = rv.try_lock() {
-                    Some(vlock.evaluate_ref(evaluator).await?)
-                } else {
-                    bail!("Cyclic dependency detected");
+        let Some(rv) = evaluator.state(resource, scope.task) else {
+            return Ok(None);
+        };
+
+        // Other tasks may be holding the lock while evaluating the same resource so the lock
+        // itself cannot be used to detect cycles, only resources resolved in this scope can.
+        {
+            let mut resolving = scope.resolving.lock().unwrap();
+            if resolving.iter().any(|r| r == resource) {
+                bail!("Cyclic dependency detected");
+            }
+            resolving.push(resource.to_string());
+        }
+
+        let result = Self::resolve_state(evaluator, scope, resource, &rv).await;
+        scope.resolving.lock().unwrap().pop();
+
+        result.map(Some)
+    }
+
+    async fn resolve_state<C>(
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+        name: &str,
+        state: &Mutex<ResourceState>,
+    ) -> Result<String>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        if let Some(value) = scope.picked.lock().unwrap().get(name) {
+            return Ok(value.clone());
+        }
+
+        let mut state = state.lock().await;
+        let dynamic = state.resource.is_refreshable()
+            || state.resource.scope == ResourceScope::Task
+            || state.resource.pool.is_some();
+
+        let values = match state.current() {
+            Some(values) => values,
+            None => {
+                let used = scope.dynamic.lock().unwrap().len();
+                let reads = scope.feeder_reads.load(Ordering::Relaxed);
+                let (values, ttl) = state
+                    .resource
+                    .clone()
+                    .evaluate(evaluator.clone(), scope)
+                    .await?;
+                let values = Arc::new(values);
+                // Values that depend on refreshable, task scoped or pooled resources must be
+                // evaluated every time so that they pick up the right values, and so must global
+                // ones that use a feeder since every request gets its own row
+                let keep = match state.resource.scope {
+                    ResourceScope::Request => false,
+                    ResourceScope::Global
+                        if scope.feeder_reads.load(Ordering::Relaxed) != reads =>
+                    {
+                        false
+                    }
+                    _ => dynamic || scope.dynamic.lock().unwrap().len() == used,
+                };
+                if keep {
+                    state.set(values.clone(), ttl);
                 }
+                if keep && ttl.is_some() && !state.refreshing {
+                    state.refreshing = true;
+                    tokio::spawn(evaluator.refresh_in_background(name.to_string(), scope.task));
+                }
+                values
             }
-            None => None,
-        })
+        };
+        let value = state
+            .pick(&values)
+            .ok_or_else(|| anyhow!("Resource {name} does not have any values"))?;
+        state.used = true;
+
+        if dynamic {
+            let entry = (name.to_string(), state.generation);
+            scope.dynamic.lock().unwrap().push(entry);
+        }
+        // All references in one request use the same value
+        if state.resource.pool.is_some() || state.resource.scope == ResourceScope::Request {
+            let mut picked = scope.picked.lock().unwrap();
+            picked.insert(name.to_string(), value.clone());
+        }
+
+        Ok(value)
     }
 }
 
+/// Something that can be referenced from inside a `${...}` format specifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference<'a> {
+    Resource(&'a str),
+    Feeder {
+        name: &'a str,
+        column: &'a str,
+    },
+    /// An environment variable with an optional default used if the variable is not set.
+    Env {
+        name: &'a str,
+        default: Option<&'a str>,
+    },
+    /// A variable passed on the command line using `--set`.
+    Var(&'a str),
+    /// The value extracted from a response.
+    Value,
+    /// The whole body of a response.
+    Body,
+    /// The status code of a response.
+    Status,
+    /// A header of a response.
+    Header(&'a str),
+}
+
+impl<'a> Reference<'a> {
+    pub fn parse(fmtspec: &'a str) -> Result<Self> {
+        if let Some(resource) = fmtspec.strip_prefix("resources.") {
+            Ok(Self::Resource(resource))
+        } else if let Some(spec) = fmtspec.strip_prefix("feeders.") {
+            let (name, column) = spec
+                .split_once('.')
+                .ok_or_else(|| anyhow!("{fmtspec} must be of the form feeders.<name>.<column>"))?;
+            Ok(Self::Feeder { name, column })
+        } else if let Some(spec) = fmtspec.strip_prefix("env.") {
+            Ok(match spec.split_once(":-") {
+                Some((name, default)) => Self::Env {
+                    name,
+                    default: Some(default),
+                },
+                None => Self::Env {
+                    name: spec,
+                    default: None,
+                },
+            })
+        } else if let Some(name) = fmtspec.strip_prefix("vars.") {
+            Ok(Self::Var(name))
+        } else if let Some(name) = fmtspec.strip_prefix("headers.") {
+            Ok(Self::Header(name))
+        } else if fmtspec == "value" {
+            Ok(Self::Value)
+        } else if fmtspec == "body" {
+            Ok(Self::Body)
+        } else if fmtspec == "status" {
+            Ok(Self::Status)
+        } else {
+            bail!(
+                "{fmtspec} must start with one of resources., feeders., env., vars. or headers. \
+                 or be one of value, body or status"
+            )
+        }
+    }
+
+    /// Whether this refers to a response and can therefore only be used in its `format`.
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self,
+            Self::Value | Self::Body | Self::Status | Self::Header(_)
+        )
+    }
+}
+
+/// What `${value}`, `${body}`, `${status}` and `${headers.<name>}` refer to.
+#[derive(Clone, Copy)]
+enum Formatting<'a> {
+    /// The response whose `format` string is being evaluated.
+    Response {
+        response: &'a Response,
+        value: &'a str,
+    },
+    /// The request whose uri, query, headers or cookies are being evaluated, only its body can be
+    /// referenced.
+    Request { body: &'a str },
+}
+
 #[async_recursion]
-async fn format_with_resources<C: Connect + Clone + Send + Sync + 'static>(
+async fn format_with_resources<C>(
     evaluator: Arc<Evaluator<C>>,
+    scope: &Scope,
     fmtstr: &str,
-) -> Result<String> {
+    // 'async_recursion is the lifetime of the boxed future, which borrows the response
+    formatting: Option<Formatting<'async_recursion>>,
+) -> Result<String>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
     format_callback(fmtstr, |fmtspec| {
         let evaluator = evaluator.clone();
         async move {
-            let resource = fmtspec
-                .strip_prefix("resources.")
-                .ok_or_else(|| anyhow!("{fmtspec} must start with resources."))?;
-
-            Value::resolve_resource(evaluator, resource)
-                .await
-                .and_then(|x| x.ok_or_else(|| anyhow!("Resource {resource} does not exist")))
+            let expression = Expression::parse(&fmtspec)?;
+            let reference = Reference::parse(expression.reference)?;
+            let value = lookup(evaluator, scope, reference, formatting).await?;
+            expression.apply(value)
         }
     })
     .await
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(untagged)]
-pub enum MaybeDeleted<V = Value> {
+/// Looks up the value of a reference, the inner error means that the value is missing and can
+/// still be replaced by a default.
+async fn lookup<C>(
+    evaluator: Arc<Evaluator<C>>,
+    scope: &Scope,
+    reference: Reference<'_>,
+    formatting: Option<Formatting<'_>>,
+) -> Result<Result<String>>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let response = match formatting {
+        Some(Formatting::Response { response, value }) => Some((response, value)),
+        Some(Formatting::Request { body }) if reference == Reference::Body => {
+            return Ok(Ok(body.to_string()));
+        }
+        _ => None,
+    };
+    if reference.is_response() && response.is_none() {
+        bail!(
+            "value, status and headers. can only be used in the format of a response, body also \
+             in the uri, query, headers and cookies of a request"
+        );
+    }
+
+    Ok(match reference {
+        Reference::Resource(resource) => Value::resolve_resource(evaluator, scope, resource)
+            .await?
+            .ok_or_else(|| anyhow!("Resource {resource} does not exist")),
+        Reference::Feeder { name, column } => {
+            let feeder = evaluator
+                .feeders
+                .get(name)
+                .ok_or_else(|| anyhow!("Feeder {name} does not exist"))?;
+
+            scope
+                .row(name, feeder)?
+                .get(column)
+                .cloned()
+                .ok_or_else(|| anyhow!("Feeder {name} does not have a column named {column}"))
+        }
+        Reference::Env { name, default } => match std::env::var(name) {
+            Ok(value) => Ok(value),
+            Err(_) => default.map(str::to_string).ok_or_else(|| {
+                anyhow!("Environment variable {name} is not set and has no default")
+            }),
+        },
+        Reference::Var(name) => {
+            evaluator.vars.get(name).cloned().ok_or_else(|| {
+                anyhow!("Variable {name} is not set, pass it using --set {name}=VALUE")
+            })
+        }
+        Reference::Value => Ok(response.unwrap().1.to_string()),
+        Reference::Body => Ok(response.unwrap().0.body.clone()),
+        Reference::Status => Ok(response.unwrap().0.status.as_u16().to_string()),
+        Reference::Header(name) => {
+            let headers = &response.unwrap().0.headers;
+            match headers.get(name).map(|value| value.to_str()) {
+                Some(Ok(value)) => Ok(value.to_string()),
+                Some(Err(_)) => Err(anyhow!("Value of the {name} header is not a string")),
+                None => Err(anyhow!("Response does not have a {name} header")),
+            }
+        }
+    })
+}
+
+/// A value that can also be `{}` to remove a header or cookie that would be inherited otherwise.
+#[derive(Debug, Clone)]
+pub enum MaybeDeleted {
     Deleted(Deleted),
-    Value(V),
+    Value(Value),
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Deleted;
 
-impl<'de> Deserialize<'de> for Deleted {
+impl<'de> Deserialize<'de> for MaybeDeleted {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        struct Visitor;
+        struct MaybeDeletedVisitor;
 
-        impl<'de> serde::de::Visitor<'de> for Visitor {
-            type Value = Deleted;
+        impl<'de> Visitor<'de> for MaybeDeletedVisitor {
+            type Value = MaybeDeleted;
 
             fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(f, "an empty map")
+                write!(
+                    f,
+                    "a string, a number, a boolean, a request table or `{{}}` to remove the value"
+                )
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                ValueVisitor.visit_str(v).map(MaybeDeleted::Value)
+            }
+
+            fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<Self::Value, E> {
+                ValueVisitor.visit_bool(v).map(MaybeDeleted::Value)
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                ValueVisitor.visit_i64(v).map(MaybeDeleted::Value)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                ValueVisitor.visit_u64(v).map(MaybeDeleted::Value)
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                ValueVisitor.visit_f64(v).map(MaybeDeleted::Value)
             }
 
             fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
             where
-                A: serde::de::MapAccess<'de>,
+                A: MapAccess<'de>,
             {
-                match map.size_hint() {
-                    Some(0) => Ok(Deleted),
-                    Some(_) => Err(serde::de::Error::invalid_value(
-                        serde::de::Unexpected::Map,
-                        &self,
-                    )),
-                    None => {
-                        if map
-                            .next_entry::<serde::de::IgnoredAny, serde::de::IgnoredAny>()?
-                            .is_some()
-                        {
-                            Err(serde::de::Error::invalid_value(
-                                serde::de::Unexpected::Map,
-                                &self,
-                            ))
-                        } else {
-                            Ok(Deleted)
-                        }
+                match map.next_key_seed(FirstFieldName(FromResponseBody::FIELDS))? {
+                    None => Ok(MaybeDeleted::Deleted(Deleted)),
+                    Some(first) if first == TOML_DATETIME => {
+                        Ok(MaybeDeleted::Value(Value::Constant(map.next_value()?)))
                     }
+                    Some(first) => Ok(MaybeDeleted::Value(Value::Request(Box::new(
+                        FromResponseBody::from_map(
+                            map,
+                            Some(first),
+                            FromResponseBody::FIELDS,
+                            |_, _| unreachable!("only request fields are allowed"),
+                        )?,
+                    )))),
                 }
             }
         }
 
-        deserializer.deserialize_map(Visitor)
+        deserializer.deserialize_any(MaybeDeletedVisitor)
     }
 }
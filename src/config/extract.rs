@@ -0,0 +1,334 @@
+use std::{borrow::Cow, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use hyper::{
+    header::{HeaderName, SET_COOKIE},
+    HeaderMap, StatusCode,
+};
+use jaq_interpret::{Ctx, Filter, FilterT, ParseCtx, RcIter, Val};
+use regex::Regex;
+use scraper::{ElementRef, Html, Node, Selector};
+use serde::{
+    de::{Error, Unexpected},
+    Deserialize, Deserializer,
+};
+use sxd_document::{
+    dom::{self, Document},
+    Package,
+};
+
+use super::serde_http;
+
+/// The parts of a response that values can be extracted from.
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+/// Describes which part of a response should become the value of a resource.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum Extract {
+    Json {
+        pointer: String,
+    },
+    Jq {
+        #[serde(deserialize_with = "jq")]
+        query: JqQuery,
+    },
+    Regex {
+        #[serde(deserialize_with = "regex")]
+        pattern: Regex,
+        group: Option<Group>,
+    },
+    Header {
+        #[serde(with = "serde_http::header_name")]
+        name: HeaderName,
+    },
+    SetCookie {
+        name: String,
+    },
+    Status,
+    Css {
+        #[serde(deserialize_with = "selector")]
+        selector: Selector,
+        attribute: Option<String>,
+    },
+    Xpath {
+        #[serde(deserialize_with = "xpath")]
+        path: XPath,
+    },
+}
+
+/// A jq query that is compiled once while reading the configuration.
+#[derive(Debug, Clone)]
+pub struct JqQuery {
+    query: String,
+    filter: Arc<Filter>,
+}
+
+impl std::fmt::Display for JqQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.query)
+    }
+}
+
+/// An XPath expression that was validated while reading the configuration, compiled expressions
+/// can't be shared between threads so it is compiled again for every response.
+#[derive(Debug, Clone)]
+pub struct XPath {
+    path: String,
+}
+
+impl XPath {
+    fn compile(path: &str) -> Option<sxd_xpath::XPath> {
+        sxd_xpath::Factory::new().build(path).ok().flatten()
+    }
+}
+
+impl std::fmt::Display for XPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+/// A capture group of a regex, either its index or its name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Group {
+    Index(usize),
+    Name(String),
+}
+
+impl std::fmt::Display for Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Group::Index(index) => write!(f, "{index}"),
+            Group::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Extract {
+    pub fn extract<'a>(&self, response: &'a Response) -> Result<Cow<'a, str>> {
+        Ok(match self {
+            Extract::Json { pointer } => {
+                let value = serde_json::from_str::<serde_json::Value>(&response.body)
+                    .context("Failed to deserialize response")?;
+
+                let val = value
+                    .pointer(pointer)
+                    .context("Response does not contain expected value")?;
+
+                Cow::Owned(json_to_string(val))
+            }
+            Extract::Jq { query } => {
+                let outputs = run_jq(query, &response.body)?;
+                let first = outputs
+                    .first()
+                    .with_context(|| format!("{query} did not produce any values"))?;
+                Cow::Owned(json_to_string(first))
+            }
+            Extract::Regex { pattern, group } => {
+                let captures = pattern
+                    .captures(&response.body)
+                    .with_context(|| format!("Response does not match {pattern}"))?;
+                // Without an explicit group the first one is used if there is one
+                let group = group
+                    .clone()
+                    .unwrap_or(Group::Index((pattern.captures_len() > 1) as usize));
+                let matched = match group {
+                    Group::Index(index) => captures.get(index),
+                    Group::Name(ref name) => captures.name(name),
+                };
+                let matched = matched.with_context(|| {
+                    format!("Group {group} of {pattern} did not match anything")
+                })?;
+                Cow::Borrowed(matched.as_str())
+            }
+            Extract::Header { name } => {
+                let value = response
+                    .headers
+                    .get(name)
+                    .with_context(|| format!("Response does not have a {name} header"))?;
+                Cow::Borrowed(
+                    value
+                        .to_str()
+                        .with_context(|| format!("Value of the {name} header is not a string"))?,
+                )
+            }
+            Extract::SetCookie { name } => {
+                let value = response
+                    .headers
+                    .get_all(SET_COOKIE)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .filter_map(|value| value.split(';').next()?.split_once('='))
+                    .find(|(cookie, _)| cookie.trim() == name)
+                    .map(|(_, value)| value.trim())
+                    .with_context(|| format!("Response does not set a cookie named {name}"))?;
+                Cow::Borrowed(value)
+            }
+            Extract::Status => Cow::Owned(response.status.as_u16().to_string()),
+            Extract::Css {
+                selector,
+                attribute,
+            } => {
+                let html = Html::parse_document(&response.body);
+                let element = html.select(selector).next().ok_or_else(|| {
+                    anyhow!("Response does not contain an element matching the selector")
+                })?;
+                Cow::Owned(match attribute {
+                    Some(attribute) => element
+                        .value()
+                        .attr(attribute)
+                        .with_context(|| format!("Element does not have a {attribute} attribute"))?
+                        .to_string(),
+                    None => element.text().collect(),
+                })
+            }
+            Extract::Xpath { path } => {
+                // HTML pages are rarely valid XML, so they are converted if parsing fails
+                let package = sxd_document::parser::parse(&response.body).unwrap_or_else(|_| {
+                    let package = Package::new();
+                    let document = package.as_document();
+                    let html = Html::parse_document(&response.body);
+                    document
+                        .root()
+                        .append_child(copy_html(&document, html.root_element()));
+                    package
+                });
+                let document = package.as_document();
+                let value = XPath::compile(&path.path)
+                    .with_context(|| format!("Failed to compile {path}"))?
+                    .evaluate(&sxd_xpath::Context::new(), document.root())
+                    .with_context(|| format!("Failed to evaluate {path}"))?;
+                Cow::Owned(match value {
+                    sxd_xpath::Value::Nodeset(nodes) => nodes
+                        .document_order_first()
+                        .with_context(|| format!("{path} does not match anything"))?
+                        .string_value(),
+                    value => value.string(),
+                })
+            }
+        })
+    }
+
+    /// Like [`Extract::extract`] but extracts every value for a pool, JSON arrays are split up into
+    /// their elements and so are the outputs of jq queries.
+    pub fn extract_list(&self, response: &Response) -> Result<Vec<String>> {
+        let mut values = match self {
+            Extract::Json { pointer } => {
+                let value = serde_json::from_str::<serde_json::Value>(&response.body)
+                    .context("Failed to deserialize response")?;
+
+                vec![value
+                    .pointer(pointer)
+                    .context("Response does not contain expected value")?
+                    .clone()]
+            }
+            Extract::Jq { query } => run_jq(query, &response.body)?,
+            other => return Ok(vec![other.extract(response)?.into_owned()]),
+        };
+
+        if let [serde_json::Value::Array(array)] = values.as_mut_slice() {
+            values = std::mem::take(array);
+        }
+        Ok(values.iter().map(json_to_string).collect())
+    }
+}
+
+/// Strings are used as is, everything else is serialized as JSON.
+pub(super) fn json_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn compile_jq(query: &str) -> Option<Filter> {
+    let mut defs = ParseCtx::new(vec![]);
+    defs.insert_natives(jaq_core::core());
+    defs.insert_defs(jaq_std::std());
+
+    let (filter, errors) = jaq_parse::parse(query, jaq_parse::main());
+    if !errors.is_empty() {
+        return None;
+    }
+    let filter = defs.compile(filter?);
+    defs.errs.is_empty().then_some(filter)
+}
+
+/// Copies an HTML element along with its text and descendant elements into `document`.
+fn copy_html<'d>(document: &Document<'d>, html: ElementRef) -> dom::Element<'d> {
+    let element = document.create_element(html.value().name());
+    for (name, value) in html.value().attrs() {
+        element.set_attribute_value(name, value);
+    }
+    for child in html.children() {
+        match child.value() {
+            Node::Text(text) => element.append_child(document.create_text(text)),
+            Node::Element(_) => {
+                // Wrapping an element node always succeeds
+                let child = ElementRef::wrap(child).unwrap();
+                element.append_child(copy_html(document, child))
+            }
+            _ => (),
+        }
+    }
+    element
+}
+
+/// Returns every output of the jq `query` when run on `body`.
+fn run_jq(query: &JqQuery, body: &str) -> Result<Vec<serde_json::Value>> {
+    let input = serde_json::from_str::<serde_json::Value>(body)
+        .context("Failed to deserialize response")?;
+
+    let inputs = RcIter::new(std::iter::empty());
+    let mut outputs = vec![];
+    for output in query.filter.run((Ctx::new([], &inputs), Val::from(input))) {
+        match output {
+            Ok(value) => outputs.push(serde_json::Value::from(value)),
+            Err(e) => bail!("Failed to run {query}: {e}"),
+        }
+    }
+    Ok(outputs)
+}
+
+fn jq<'de, D: Deserializer<'de>>(de: D) -> Result<JqQuery, D::Error> {
+    let query = String::deserialize(de)?;
+    match compile_jq(&query) {
+        Some(filter) => Ok(JqQuery {
+            query,
+            filter: Arc::new(filter),
+        }),
+        None => Err(Error::invalid_value(
+            Unexpected::Str(&query),
+            &"a valid jq query",
+        )),
+    }
+}
+
+fn regex<'de, D: Deserializer<'de>>(de: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(de)?;
+    Regex::new(&pattern).map_err(|e| Error::custom(format_args!("invalid regex: {e}")))
+}
+
+fn selector<'de, D: Deserializer<'de>>(de: D) -> Result<Selector, D::Error> {
+    let selector = String::deserialize(de)?;
+    Selector::parse(&selector)
+        .map_err(|_| Error::invalid_value(Unexpected::Str(&selector), &"a valid CSS selector"))
+}
+
+fn xpath<'de, D: Deserializer<'de>>(de: D) -> Result<XPath, D::Error> {
+    let path = String::deserialize(de)?;
+    match XPath::compile(&path) {
+        Some(_) => Ok(XPath { path }),
+        None => Err(Error::invalid_value(
+            Unexpected::Str(&path),
+            &"a valid XPath expression",
+        )),
+    }
+}
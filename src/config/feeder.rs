@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+pub type Row = HashMap<String, String>;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Go through the rows in order, wrapping around at the end.
+    #[default]
+    Sequential,
+    /// Pick a random row every time.
+    Random,
+    /// Go through the rows in order, every row is used at most once.
+    Unique,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Cursor {
+    /// All tasks share a single position in the file.
+    #[default]
+    Shared,
+    /// Every task goes through the file independently.
+    Task,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeederInfo {
+    pub file: PathBuf,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub cursor: Cursor,
+}
+
+/// Returned when a feeder with the [`Strategy::Unique`] strategy has no rows left.
+#[derive(Debug)]
+pub struct Exhausted(pub String);
+
+impl std::fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Feeder {} has been exhausted", self.0)
+    }
+}
+
+impl std::error::Error for Exhausted {}
+
+pub struct Feeder {
+    name: String,
+    rows: Vec<Arc<Row>>,
+    strategy: Strategy,
+    cursor: Cursor,
+    shared: AtomicUsize,
+    tasks: std::sync::Mutex<HashMap<usize, usize>>,
+}
+
+impl Feeder {
+    pub fn load(name: String, info: FeederInfo, base: &Path) -> Result<Self> {
+        let path = base.join(&info.file);
+        let rows = match path.extension().and_then(|x| x.to_str()) {
+            Some("csv") => read_csv(&path),
+            Some("jsonl" | "ndjson") => read_jsonl(&path),
+            _ => bail!(
+                "Could not determine format of {}, expected a .csv or .jsonl file",
+                path.display()
+            ),
+        }
+        .with_context(|| format!("Could not read feeder file {}", path.display()))?;
+
+        if rows.is_empty() {
+            bail!("Feeder file {} does not contain any rows", path.display());
+        }
+
+        Ok(Self {
+            name,
+            rows: rows.into_iter().map(Arc::new).collect(),
+            strategy: info.strategy,
+            cursor: info.cursor,
+            shared: AtomicUsize::new(0),
+            tasks: Default::default(),
+        })
+    }
+
+    /// Whether every row of this feeder has a value for `column`.
+    pub fn has_column(&self, column: &str) -> bool {
+        self.rows.iter().all(|row| row.contains_key(column))
+    }
+
+    /// Picks the next row for the task with index `task`.
+    pub fn next(&self, task: usize) -> Result<Arc<Row>, Exhausted> {
+        if self.strategy == Strategy::Random {
+            return Ok(self.rows[rand::random::<usize>() % self.rows.len()].clone());
+        }
+
+        let index = match self.cursor {
+            Cursor::Shared => self.shared.fetch_add(1, Ordering::Relaxed),
+            Cursor::Task => {
+                let mut tasks = self.tasks.lock().unwrap();
+                let position = tasks.entry(task).or_default();
+                *position += 1;
+                *position - 1
+            }
+        };
+
+        match self.strategy {
+            Strategy::Unique => self
+                .rows
+                .get(index)
+                .cloned()
+                .ok_or_else(|| Exhausted(self.name.clone())),
+            _ => Ok(self.rows[index % self.rows.len()].clone()),
+        }
+    }
+}
+
+fn read_csv(path: &Path) -> Result<Vec<Row>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record?;
+        rows.push(
+            headers
+                .iter()
+                .zip(record.iter())
+                .map(|(h, v)| (h.to_string(), v.to_string()))
+                .collect(),
+        );
+    }
+
+    Ok(rows)
+}
+
+fn read_jsonl(path: &Path) -> Result<Vec<Row>> {
+    let mut rows = vec![];
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&line)
+            .with_context(|| format!("Line {} is not a valid JSON object", i + 1))?;
+
+        rows.push(
+            object
+                .into_iter()
+                .map(|(k, v)| match v {
+                    serde_json::Value::String(s) => (k, s),
+                    other => (k, other.to_string()),
+                })
+                .collect(),
+        );
+    }
+
+    Ok(rows)
+}
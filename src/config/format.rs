@@ -1,6 +1,10 @@
 use std::future::Future;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use super::extract::json_to_string;
 
 pub fn format_one(mut fmtstr: String, value: &str) -> Result<String> {
     let mut format_spec_index = None;
@@ -34,10 +38,16 @@ pub fn format_one(mut fmtstr: String, value: &str) -> Result<String> {
     }
 }
 
-pub async fn format_callback<FF: Future<Output = Result<String>>, F: FnMut(String) -> FF>(
-    fmtstr: &str,
-    mut callback: F,
-) -> Result<String> {
+/// A part of a format string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Literal(String),
+    Spec(String),
+}
+
+/// Splits a format string into literal text and `${...}` format specifiers.
+pub fn parse(fmtstr: &str) -> Result<Vec<Segment>> {
+    let mut segments = vec![];
     let mut out = String::with_capacity(fmtstr.len());
 
     enum State {
@@ -50,13 +60,19 @@ pub async fn format_callback<FF: Future<Output = Result<String>>, F: FnMut(Strin
     loop {
         match (&mut state, it.next()) {
             (State::Normal, Some('$')) => match it.peek() {
-                Some('$') => out.push('$'),
+                Some('$') => {
+                    it.next();
+                    out.push('$')
+                }
                 Some('{') => {
                     #[cfg(debug_assertions)]
                     assert_eq!(it.next(), Some('{'));
                     #[cfg(not(debug_assertions))]
                     it.next();
 
+                    if !out.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut out)));
+                    }
                     state = State::Spec {
                         value: String::new(),
                     };
@@ -77,7 +93,7 @@ pub async fn format_callback<FF: Future<Output = Result<String>>, F: FnMut(Strin
                     State::Spec { value } => value,
                 };
 
-                out.push_str(&callback(value).await?)
+                segments.push(Segment::Spec(value));
             }
             (State::Spec { value }, Some(c)) => value.push(c),
             (State::Spec { .. }, None) => {
@@ -86,5 +102,281 @@ pub async fn format_callback<FF: Future<Output = Result<String>>, F: FnMut(Strin
         }
     }
 
+    if !out.is_empty() {
+        segments.push(Segment::Literal(out));
+    }
+
+    Ok(segments)
+}
+
+/// Whether the `format` of a response uses `${...}` format specifiers instead of a single `{}`.
+pub fn is_template(fmtstr: &str) -> bool {
+    fmtstr.contains("${")
+}
+
+/// Returns the value of a format string that does not contain any format specifiers.
+pub fn literal(fmtstr: &str) -> Option<String> {
+    let mut out = String::new();
+    for segment in parse(fmtstr).ok()? {
+        match segment {
+            Segment::Literal(literal) => out.push_str(&literal),
+            Segment::Spec(_) => return None,
+        }
+    }
+    Some(out)
+}
+
+pub async fn format_callback<FF: Future<Output = Result<String>>, F: FnMut(String) -> FF>(
+    fmtstr: &str,
+    mut callback: F,
+) -> Result<String> {
+    let mut out = String::with_capacity(fmtstr.len());
+
+    for segment in parse(fmtstr)? {
+        match segment {
+            Segment::Literal(literal) => out.push_str(&literal),
+            Segment::Spec(spec) => out.push_str(&callback(spec).await?),
+        }
+    }
+
     Ok(out)
 }
+
+/// The names of all filters that can be used in a format specifier.
+pub const FILTERS: &[&str] = &[
+    "base64",
+    "urlencode",
+    "json_escape",
+    "sha256",
+    "upper",
+    "lower",
+    "trim",
+    "json",
+    "default",
+];
+
+/// A function applied to a value inside of a format specifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Base64,
+    Urlencode,
+    JsonEscape,
+    /// The lowercase hex encoded SHA-256 digest.
+    Sha256,
+    Upper,
+    Lower,
+    Trim,
+    /// Parses the value as JSON and extracts the value at a JSON pointer.
+    Json(String),
+    /// Replaces the value if it is missing.
+    Default(String),
+}
+
+impl Filter {
+    fn new(name: &str, args: Vec<String>) -> Result<Self> {
+        let filter = match name {
+            "base64" => Self::Base64,
+            "urlencode" => Self::Urlencode,
+            "json_escape" => Self::JsonEscape,
+            "sha256" => Self::Sha256,
+            "upper" => Self::Upper,
+            "lower" => Self::Lower,
+            "trim" => Self::Trim,
+            "json" | "default" => {
+                let [arg] = <[String; 1]>::try_from(args)
+                    .map_err(|_| anyhow!("{name} expects exactly one string argument"))?;
+                return Ok(match name {
+                    "json" => Self::Json(arg),
+                    _ => Self::Default(arg),
+                });
+            }
+            name => bail!(
+                "Unknown filter '{name}', expected one of {}",
+                FILTERS.join(", ")
+            ),
+        };
+
+        if !args.is_empty() {
+            bail!("{name} does not take any arguments");
+        }
+        Ok(filter)
+    }
+
+    /// Applies the filter to a value, an error means that the value is missing which only
+    /// [`Filter::Default`] can recover from.
+    pub fn apply(&self, value: Result<String>) -> Result<String> {
+        let value = match (self, value) {
+            (Filter::Default(default), Err(_)) => return Ok(default.clone()),
+            (_, value) => value?,
+        };
+
+        Ok(match self {
+            Filter::Base64 => base64::engine::general_purpose::STANDARD.encode(value),
+            Filter::Urlencode => urlencoding::encode(&value).into_owned(),
+            Filter::JsonEscape => {
+                let quoted = serde_json::Value::String(value).to_string();
+                quoted[1..quoted.len() - 1].to_string()
+            }
+            Filter::Sha256 => Sha256::digest(value)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+            Filter::Upper => value.to_uppercase(),
+            Filter::Lower => value.to_lowercase(),
+            Filter::Trim => value.trim().to_string(),
+            Filter::Json(pointer) => {
+                let json = serde_json::from_str::<serde_json::Value>(&value)
+                    .context("Value is not valid JSON")?;
+                json_to_string(
+                    json.pointer(pointer)
+                        .with_context(|| format!("Value does not contain {pointer}"))?,
+                )
+            }
+            Filter::Default(_) => value,
+        })
+    }
+}
+
+/// The contents of a format specifier, a reference followed by the filters applied to its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression<'a> {
+    pub reference: &'a str,
+    pub filters: Vec<Filter>,
+}
+
+impl<'a> Expression<'a> {
+    /// Parses a format specifier like `resources.token | base64`, filters can also be called like
+    /// functions (`sha256(body)`) with any arguments following the value
+    /// (`default(env.USER, "nobody")`).
+    pub fn parse(spec: &'a str) -> Result<Self> {
+        let mut parser = Parser { text: spec, pos: 0 };
+        let expression = parser.expression()?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.peek() {
+            bail!("Unexpected '{c}' in format specifier {spec}");
+        }
+        Ok(expression)
+    }
+
+    /// Applies all filters in order.
+    pub fn apply(&self, value: Result<String>) -> Result<String> {
+        self.filters
+            .iter()
+            .fold(value, |value, filter| filter.apply(value))
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.text.len() - self.rest().trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.eat(c) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(found) => bail!("Expected '{c}' but found '{found}' in format specifier"),
+            None => bail!("Expected '{c}' but the format specifier ended"),
+        }
+    }
+
+    /// Reads a reference or the name of a filter.
+    fn word(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest.find(['(', ')', '|', ',', '"']).unwrap_or(rest.len());
+        self.pos += len;
+        rest[..len].trim_end()
+    }
+
+    /// Reads a double quoted string, `\` escapes the next character.
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                '\\' => match chars.next() {
+                    Some((_, c)) => out.push(c),
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+        bail!("Unterminated string in format specifier")
+    }
+
+    fn arguments(&mut self) -> Result<Vec<String>> {
+        let mut args = vec![];
+        if self.eat(')') {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.string()?);
+            if self.eat(')') {
+                return Ok(args);
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression<'a>> {
+        let word = self.word();
+        let mut expression = if self.eat('(') {
+            let mut inner = self.expression()?;
+            let args = if self.eat(',') {
+                self.arguments()?
+            } else {
+                self.expect(')')?;
+                vec![]
+            };
+            inner.filters.push(Filter::new(word, args)?);
+            inner
+        } else {
+            Expression {
+                reference: word,
+                filters: vec![],
+            }
+        };
+
+        while self.eat('|') {
+            let name = self.word();
+            let args = if self.eat('(') {
+                self.arguments()?
+            } else {
+                vec![]
+            };
+            expression.filters.push(Filter::new(name, args)?);
+        }
+
+        Ok(expression)
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use hyper::client::connect::Connect;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::de::{Error, MapAccess};
+
+use super::{
+    eval::{Evaluator, Scope, Value},
+    evaluate_json,
+    resource::WrappedDuration,
+    FieldName,
+};
+
+/// A resource that mints a JSON Web Token instead of requesting one.
+#[derive(Debug, Clone)]
+pub struct Jwt {
+    pub algorithm: Algorithm,
+    /// The secret of HMAC algorithms, if it is not read from `key_file`.
+    pub secret: Option<Value>,
+    /// A PEM encoded private key, or the secret of HMAC algorithms.
+    pub key_file: Option<PathBuf>,
+    /// The key read from `key_file`, see [`Jwt::load`].
+    key: Option<SigningKey>,
+    /// A table whose strings are formatted, `iat` and `exp` are added unless they are set.
+    pub claims: serde_json::Value,
+    pub kid: Option<Value>,
+    /// How long a token is valid.
+    pub expires_in: Duration,
+}
+
+/// Keys are read once so that minting a token for every request stays cheap.
+#[derive(Clone)]
+struct SigningKey(EncodingKey);
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SigningKey")
+    }
+}
+
+impl Jwt {
+    pub const FIELDS: &'static [&'static str] = &[
+        "algorithm",
+        "secret",
+        "key_file",
+        "claims",
+        "kid",
+        "expires_in",
+    ];
+
+    /// Deserializes the rest of a resource table, keys that are in `fields` but not in
+    /// [`Jwt::FIELDS`] are passed to `other`.
+    pub fn from_map<'de, A>(
+        map: &mut A,
+        fields: &'static [&'static [&'static str]],
+        mut other: impl FnMut(&str, &mut A) -> Result<(), A::Error>,
+    ) -> Result<Self, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut algorithm = None;
+        let mut secret = None;
+        let mut key_file = None;
+        let mut claims = None;
+        let mut kid = None;
+        let mut expires_in = None;
+
+        while let Some(key) = map.next_key_seed(FieldName(fields))? {
+            match key.as_str() {
+                "algorithm" => algorithm = Some(map.next_value()?),
+                "secret" => secret = Some(map.next_value()?),
+                "key_file" => key_file = Some(map.next_value()?),
+                "claims" => claims = Some(map.next_value()?),
+                "kid" => kid = Some(map.next_value()?),
+                "expires_in" => expires_in = Some(map.next_value::<WrappedDuration>()?.0),
+                key => other(key, map)?,
+            }
+        }
+
+        let algorithm = algorithm.ok_or_else(|| A::Error::missing_field("algorithm"))?;
+        match (is_hmac(algorithm), &secret, &key_file) {
+            (true, Some(_), Some(_)) => {
+                return Err(A::Error::custom(
+                    "`secret` and `key_file` cannot both be set",
+                ))
+            }
+            (true, None, None) => {
+                return Err(A::Error::custom(format_args!(
+                    "{algorithm:?} requires either a `secret` or a `key_file`"
+                )))
+            }
+            (false, Some(_), _) => {
+                return Err(A::Error::custom(format_args!(
+                    "{algorithm:?} requires a `key_file` instead of a `secret`"
+                )))
+            }
+            (false, None, None) => return Err(A::Error::missing_field("key_file")),
+            _ => (),
+        }
+
+        let claims = claims.unwrap_or_else(|| serde_json::Value::Object(Default::default()));
+        if !claims.is_object() {
+            return Err(A::Error::custom("`claims` has to be a table"));
+        }
+
+        Ok(Self {
+            algorithm,
+            secret,
+            key_file,
+            key: None,
+            claims,
+            kid,
+            expires_in: expires_in.unwrap_or(Duration::from_secs(60 * 60)),
+        })
+    }
+
+    /// Reads the key from `key_file`.
+    pub fn load(&mut self) -> Result<()> {
+        let Some(ref path) = self.key_file else {
+            return Ok(());
+        };
+
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Could not read key file {}", path.display()))?;
+        let key = match self.algorithm {
+            // Trimmed like file resources so that a trailing newline is not part of the secret
+            algorithm if is_hmac(algorithm) => Ok(EncodingKey::from_secret(bytes.trim_ascii())),
+            Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(&bytes),
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&bytes),
+            _ => EncodingKey::from_rsa_pem(&bytes),
+        }
+        .with_context(|| {
+            format!(
+                "{} is not a valid PEM encoded {:?} key",
+                path.display(),
+                self.algorithm
+            )
+        })?;
+
+        self.key = Some(SigningKey(key));
+        Ok(())
+    }
+
+    /// Mints a new token, returns it along with how long it is valid.
+    pub async fn mint<C>(
+        self,
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+    ) -> Result<(String, Duration)>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let key = match (self.key, self.secret) {
+            (Some(SigningKey(key)), _) => key,
+            (None, Some(secret)) => {
+                let secret = secret
+                    .evaluate(evaluator.clone(), scope)
+                    .await
+                    .context("Failed to resolve value for secret")?;
+                EncodingKey::from_secret(secret.as_bytes())
+            }
+            (None, None) => bail!("The key of the token has not been loaded"),
+        };
+
+        let mut header = Header::new(self.algorithm);
+        if let Some(kid) = self.kid {
+            header.kid = Some(
+                kid.evaluate(evaluator.clone(), scope)
+                    .await
+                    .context("Failed to resolve value for kid")?,
+            );
+        }
+
+        let claims = evaluate_json(self.claims, evaluator, scope)
+            .await
+            .context("Failed to resolve value for claims")?;
+        let serde_json::Value::Object(mut claims) = claims else {
+            unreachable!("claims are checked to be a table");
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        claims.entry("iat").or_insert(now.into());
+        claims
+            .entry("exp")
+            .or_insert((now + self.expires_in.as_secs()).into());
+
+        let token = jsonwebtoken::encode(&header, &claims, &key).context("Failed to sign token")?;
+        Ok((token, self.expires_in))
+    }
+}
+
+fn is_hmac(algorithm: Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    )
+}
@@ -1,48 +1,132 @@
-use std::{collections::HashMap, hash::Hash, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    hash::Hash,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use async_recursion::async_recursion;
 use hyper::{
-    client::connect::Connect, header::COOKIE, http::HeaderValue, HeaderMap, Method, Request, Uri,
+    client::connect::Connect,
+    header::{AUTHORIZATION, CONTENT_TYPE, COOKIE},
+    http::HeaderValue,
+    HeaderMap, Method, Request, Uri,
+};
+use serde::{
+    de::{DeserializeSeed, MapAccess, Unexpected, Visitor},
+    Deserialize,
 };
-use serde::Deserialize;
 
-use crate::{config::eval::Value, cookie::Cookie, USER_AGENT};
+use crate::{
+    cli::{ConfigFormat, Overrides},
+    config::eval::Value,
+    cookie::{Cookie, JarScope},
+    USER_AGENT,
+};
 
+pub mod auth;
 pub mod eval;
+pub mod extract;
+pub mod feeder;
 pub mod format;
+pub mod jwt;
+pub mod resource;
 pub mod serde_http;
-use eval::{Evaluator, MaybeDeleted};
+pub mod sign;
+pub mod template;
+pub mod walk;
+use auth::{Auth, Authorization, Credentials};
+use eval::{Evaluator, MaybeDeleted, Scope};
+use feeder::FeederInfo;
+use resource::Resource;
+use sign::{Sign, Signer};
+use template::{Entry, Template};
 
 #[derive(Debug, Clone)]
 pub struct HammerFile {
-    pub resources: HashMap<String, Value>,
+    pub cookie_jar: Option<JarScope>,
+    pub resources: HashMap<String, Resource>,
+    pub feeders: HashMap<String, FeederInfo>,
     pub hammer: Vec<HammerInfo>,
+    /// The included files resources are from, resources of the parsed file are not included.
+    pub resource_files: HashMap<String, PathBuf>,
+    /// The included files templates are from, like [`HammerFile::resource_files`].
+    pub template_files: HashMap<String, PathBuf>,
 }
 
 impl HammerFile {
-    pub fn parse_toml(text: &str) -> Result<HammerFile, toml::de::Error> {
-        #[derive(Deserialize)]
-        struct Raw {
-            #[serde(default)]
-            cookies: HashMap<String, String>,
-            #[serde(with = "serde_http::generic_header_map", default)]
-            headers: HeaderMap<String>,
-            #[serde(default)]
-            resources: HashMap<String, Value>,
-            hammer: Vec<HammerInfo>,
+    /// Reads the configuration at `path`, or from stdin if `path` is `-`.
+    pub fn read(path: &Path) -> Result<String> {
+        let mut buf = String::new();
+        if path == Path::new("-") {
+            std::io::stdin()
+                .read_to_string(&mut buf)
+                .context("Could not read urls file from stdin")?;
+        } else {
+            File::open(path)
+                .context("Could not open urls file")?
+                .read_to_string(&mut buf)
+                .context("Could not read urls file")?;
         }
+        Ok(buf)
+    }
 
-        let raw: Raw = toml::from_str(text)?;
-        let mut hammers = raw.hammer;
+    /// Resolves [`ConfigFormat::Auto`] to the format indicated by the extension of `path`.
+    pub fn format(path: &Path, format: ConfigFormat) -> ConfigFormat {
+        match format {
+            ConfigFormat::Auto => match path.extension().and_then(|x| x.to_str()) {
+                Some("yaml" | "yml") => ConfigFormat::Yaml,
+                Some("json") => ConfigFormat::Json,
+                _ => ConfigFormat::Toml,
+            },
+            other => other,
+        }
+    }
+
+    /// Parses a configuration, files it includes are resolved relative to `base`.
+    pub fn parse(text: &str, format: ConfigFormat, base: &Path) -> Result<HammerFile> {
+        let mut raw = RawFile::parse(text, format)?.include(base, &mut vec![])?;
+
+        for (name, resource) in raw.resources.iter_mut() {
+            resource.rebase(base);
+            resource
+                .load()
+                .with_context(|| format!("Invalid resource {name}"))?;
+        }
+
+        let mut hammers = vec![];
+        for (Entry(mut entry), mut origin) in raw.hammer.into_iter().zip(raw.entry_origins) {
+            let mut hammer = entry
+                .apply(&raw.templates)
+                .and_then(|templates| {
+                    origin.templates = templates;
+                    Ok(entry.finish::<serde::de::value::Error>()?)
+                })
+                .with_context(|| match origin.file {
+                    Some(ref file) => {
+                        format!(
+                            "Invalid entry hammer[{}] in {}",
+                            origin.index,
+                            file.display()
+                        )
+                    }
+                    None => format!("Invalid entry hammer[{}]", origin.index),
+                })?;
+            hammer.origin = origin;
+            hammers.push(hammer);
+        }
 
+        // Global values are format strings just like the ones of every entry
         for hammer in hammers.iter_mut() {
             for (key, value) in raw.cookies.iter() {
                 hammer
                     .request
                     .cookies
                     .entry(key.to_string())
-                    .or_insert_with(|| MaybeDeleted::Value(value.to_string().into()));
+                    .or_insert_with(|| MaybeDeleted::Value(Value::Formatted(value.clone())));
             }
 
             for (key, value) in raw.headers.iter() {
@@ -50,15 +134,200 @@ impl HammerFile {
                     .request
                     .headers
                     .entry(key)
-                    .or_insert_with(|| MaybeDeleted::Value(value.to_string().into()));
+                    .or_insert_with(|| MaybeDeleted::Value(Value::Formatted(value.clone())));
             }
         }
 
         Ok(HammerFile {
+            cookie_jar: raw.cookie_jar,
             resources: raw.resources,
+            feeders: raw.feeders,
             hammer: hammers,
+            resource_files: raw.resource_files,
+            template_files: raw.template_files,
         })
     }
+
+    /// Removes entries excluded by `--only`, `--skip` or `--tags` and adjusts the counts of the
+    /// remaining ones.
+    pub fn apply_overrides(&mut self, overrides: &Overrides) -> Result<()> {
+        let total = self.hammer.len();
+        self.hammer.retain(|hammer| {
+            (overrides.only.is_empty() || overrides.only.iter().any(|g| glob(g, &hammer.name)))
+                && !overrides.skip.iter().any(|g| glob(g, &hammer.name))
+                && (overrides.tags.is_empty()
+                    || overrides.tags.iter().any(|t| hammer.tags.contains(t)))
+        });
+        if self.hammer.is_empty() && total > 0 {
+            bail!("None of the {total} entries match the given filters");
+        }
+
+        for hammer in self.hammer.iter_mut() {
+            if let Some(count) = overrides.count_override {
+                hammer.count = count;
+            } else if let Some(scale) = overrides.count_scale {
+                // Scaling should never remove an entry completely
+                hammer.count =
+                    ((hammer.count as f64 * scale).round() as u64).max(hammer.count.min(1));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `name` matches `pattern` where `*` matches any number of characters and `?` matches
+/// exactly one.
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    // Position of the last `*` and the position in `name` it was tried at
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The contents of a single configuration file.
+#[derive(Default, Deserialize)]
+struct RawFile {
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    cookies: BTreeMap<String, String>,
+    #[serde(default)]
+    cookie_jar: Option<JarScope>,
+    #[serde(with = "serde_http::generic_header_map", default)]
+    headers: HeaderMap<String>,
+    #[serde(default)]
+    resources: HashMap<String, Resource>,
+    #[serde(default)]
+    feeders: HashMap<String, FeederInfo>,
+    #[serde(default)]
+    templates: HashMap<String, Template>,
+    #[serde(default)]
+    hammer: Vec<Entry>,
+    /// Where every entry of `hammer` is from.
+    #[serde(skip)]
+    entry_origins: Vec<Origin>,
+    #[serde(skip)]
+    resource_files: HashMap<String, PathBuf>,
+    #[serde(skip)]
+    template_files: HashMap<String, PathBuf>,
+}
+
+impl RawFile {
+    fn parse(text: &str, format: ConfigFormat) -> Result<RawFile> {
+        let mut raw: RawFile = match format {
+            ConfigFormat::Auto | ConfigFormat::Toml => toml::from_str(text)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(text)?,
+            ConfigFormat::Json => serde_json::from_str(text)?,
+        };
+        raw.entry_origins = (0..raw.hammer.len())
+            .map(|index| Origin {
+                index,
+                ..Default::default()
+            })
+            .collect();
+        Ok(raw)
+    }
+
+    /// Records that everything defined in this file comes from the included file at `path`.
+    fn set_file(&mut self, path: &Path) {
+        for origin in self.entry_origins.iter_mut() {
+            origin.file = Some(path.to_path_buf());
+        }
+        for name in self.resources.keys() {
+            self.resource_files.insert(name.clone(), path.to_path_buf());
+        }
+        for name in self.templates.keys() {
+            self.template_files.insert(name.clone(), path.to_path_buf());
+        }
+    }
+
+    /// Merges the files included by this one into it, `stack` contains the files that are
+    /// currently being included.
+    fn include(mut self, base: &Path, stack: &mut Vec<PathBuf>) -> Result<RawFile> {
+        let mut merged = RawFile::default();
+
+        for path in std::mem::take(&mut self.include) {
+            let path = base.join(path);
+            let canonical = path
+                .canonicalize()
+                .with_context(|| format!("Could not open included file {}", path.display()))?;
+            if stack.contains(&canonical) {
+                bail!("{} is included recursively", path.display());
+            }
+
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read included file {}", path.display()))?;
+            let mut included = RawFile::parse(&text, HammerFile::format(&path, ConfigFormat::Auto))
+                .with_context(|| format!("Could not parse included file {}", path.display()))?;
+            included.set_file(&path);
+
+            // Paths in the included file are relative to that file
+            let dir = canonical.parent().unwrap_or(Path::new("/")).to_path_buf();
+            for feeder in included.feeders.values_mut() {
+                feeder.file = dir.join(&feeder.file);
+            }
+            for resource in included.resources.values_mut() {
+                resource.rebase(&dir);
+            }
+
+            stack.push(canonical);
+            merged.merge(included.include(&dir, stack)?);
+            stack.pop();
+        }
+
+        merged.merge(self);
+        Ok(merged)
+    }
+
+    /// Adds everything from `other` to this file, values from `other` take precedence.
+    fn merge(&mut self, other: RawFile) {
+        self.cookies.extend(other.cookies);
+        self.cookie_jar = other.cookie_jar.or(self.cookie_jar);
+        for (name, value) in other.headers.into_iter() {
+            // Only the first value of every header has a name
+            if let Some(name) = name {
+                self.headers.insert(name, value);
+            }
+        }
+        for name in other.resources.keys() {
+            self.resource_files.remove(name);
+        }
+        for name in other.templates.keys() {
+            self.template_files.remove(name);
+        }
+        self.resources.extend(other.resources);
+        self.resource_files.extend(other.resource_files);
+        self.feeders.extend(other.feeders);
+        self.templates.extend(other.templates);
+        self.template_files.extend(other.template_files);
+        self.hammer.extend(other.hammer);
+        self.entry_origins.extend(other.entry_origins);
+    }
 }
 
 fn method_get() -> Method {
@@ -69,19 +338,259 @@ fn boxed_empty_value() -> Box<Value> {
     Box::new(Value::empty())
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct RequestInfo {
-    #[serde(with = "serde_http::uri")]
-    pub uri: Uri,
-    #[serde(with = "serde_http::method", default = "method_get")]
+    pub uri: Value,
     pub method: Method,
-    #[serde(default = "HashMap::new")]
-    pub cookies: HashMap<String, MaybeDeleted>,
-    #[serde(with = "serde_http::generic_header_map", default)]
+    pub query: BTreeMap<String, MaybeDeleted>,
+    pub cookies: BTreeMap<String, MaybeDeleted>,
     pub headers: HeaderMap<MaybeDeleted>,
+    /// Whether cookie names and values are percent encoded, can be disabled for values that are
+    /// already encoded.
+    pub encode_cookies: bool,
+    pub auth: Option<Auth>,
+    pub sign: Option<Sign>,
     // This has to be boxed since a Value may eventually contain another Value
-    #[serde(default = "boxed_empty_value")]
     pub body: Box<Value>,
+    /// A structured body that is sent as JSON instead of `body`, strings inside it are formatted.
+    pub body_json: Option<serde_json::Value>,
+}
+
+/// A key of a table that must be one of a few known field names.
+///
+/// Unknown keys are rejected while the key itself is being deserialized so that the error points
+/// at the misspelled key instead of at the whole table.
+pub struct FieldName(pub &'static [&'static [&'static str]]);
+
+impl<'de> DeserializeSeed<'de> for FieldName {
+    type Value = String;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FieldName {
+    type Value = String;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a field name")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let fields = self.0.iter().copied().flatten();
+        if fields.clone().any(|x| *x == v) {
+            return Ok(v.to_string());
+        }
+
+        let expected = fields.map(|x| format!("`{x}`")).collect::<Vec<_>>();
+        Err(E::custom(format_args!(
+            "unknown field `{v}`, expected one of {}",
+            expected.join(", ")
+        )))
+    }
+}
+
+/// The single key of the table that toml hands out datetimes as.
+pub const TOML_DATETIME: &str = "$__toml_private_datetime";
+
+/// A [`FieldName`] for the first key of a table which may also be a datetime, see
+/// [`TOML_DATETIME`].
+pub struct FirstFieldName(pub &'static [&'static [&'static str]]);
+
+impl<'de> DeserializeSeed<'de> for FirstFieldName {
+    type Value = String;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FirstFieldName {
+    type Value = String;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a field name")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        match v {
+            TOML_DATETIME => Ok(v.to_string()),
+            v => FieldName(self.0).visit_str(v),
+        }
+    }
+}
+
+impl RequestInfo {
+    /// Keys of a table that are used to build the request.
+    pub const FIELDS: &'static [&'static str] = &[
+        "uri",
+        "query",
+        "method",
+        "cookies",
+        "encode_cookies",
+        "headers",
+        "auth",
+        "sign",
+        "body",
+        "body_json",
+    ];
+
+    /// Deserializes a table that describes a request, see [`PartialRequest::from_map`].
+    pub fn from_map<'de, A>(
+        map: A,
+        first: Option<String>,
+        fields: &'static [&'static [&'static str]],
+        other: impl FnMut(&str, &mut A) -> Result<(), A::Error>,
+    ) -> Result<Self, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        PartialRequest::from_map(map, first, fields, other)?.finish()
+    }
+}
+
+/// A [`RequestInfo`] that may be missing some properties which will be filled in from a template.
+#[derive(Debug, Clone, Default)]
+pub struct PartialRequest {
+    pub uri: Option<Value>,
+    pub method: Option<Method>,
+    pub query: BTreeMap<String, MaybeDeleted>,
+    pub cookies: BTreeMap<String, MaybeDeleted>,
+    pub headers: HeaderMap<MaybeDeleted>,
+    pub encode_cookies: Option<bool>,
+    pub auth: Option<Auth>,
+    pub sign: Option<Sign>,
+    pub body: Option<Box<Value>>,
+    pub body_json: Option<serde_json::Value>,
+}
+
+impl PartialRequest {
+    /// Deserializes a table that describes a request, possibly along with some other keys.
+    ///
+    /// `first` is a key that has already been read from `map`, keys that are not part of the
+    /// request have to be one of `fields` and are passed to `other` which must consume their value.
+    pub fn from_map<'de, A>(
+        mut map: A,
+        first: Option<String>,
+        fields: &'static [&'static [&'static str]],
+        mut other: impl FnMut(&str, &mut A) -> Result<(), A::Error>,
+    ) -> Result<Self, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(transparent)]
+        struct WrappedMethod(#[serde(with = "serde_http::method")] Method);
+        #[derive(Deserialize)]
+        #[serde(transparent)]
+        struct WrappedHeaders(
+            #[serde(with = "serde_http::generic_header_map")] HeaderMap<MaybeDeleted>,
+        );
+
+        let mut request = PartialRequest::default();
+
+        let mut key = first;
+        if key.is_none() {
+            key = map.next_key_seed(FieldName(fields))?;
+        }
+        while let Some(name) = key {
+            match name.as_str() {
+                "uri" => {
+                    let uri = map.next_value()?;
+                    // Uris that do not interpolate anything can be validated right away
+                    if let Value::Formatted(ref fmtstr) = uri {
+                        if let Some(literal) = format::literal(fmtstr) {
+                            if literal.parse::<Uri>().is_err() {
+                                return Err(serde::de::Error::invalid_value(
+                                    Unexpected::Str(&literal),
+                                    &"a valid uri",
+                                ));
+                            }
+                        }
+                    }
+                    request.uri = Some(uri);
+                }
+                "method" => request.method = Some(map.next_value::<WrappedMethod>()?.0),
+                "query" => request.query = map.next_value()?,
+                "cookies" => request.cookies = map.next_value()?,
+                "encode_cookies" => request.encode_cookies = Some(map.next_value()?),
+                "headers" => request.headers = map.next_value::<WrappedHeaders>()?.0,
+                "auth" => request.auth = Some(map.next_value()?),
+                "sign" => request.sign = Some(map.next_value()?),
+                "body" => request.body = Some(map.next_value()?),
+                "body_json" => request.body_json = Some(map.next_value()?),
+                name => other(name, &mut map)?,
+            }
+            key = map.next_key_seed(FieldName(fields))?;
+        }
+
+        if request.body.is_some() && request.body_json.is_some() {
+            return Err(serde::de::Error::custom(
+                "`body` and `body_json` cannot both be set",
+            ));
+        }
+
+        Ok(request)
+    }
+
+    /// Fills in everything that is not set in this request from `base`.
+    pub fn merge(&mut self, base: &PartialRequest) {
+        if self.uri.is_none() {
+            self.uri = base.uri.clone();
+        }
+        if self.method.is_none() {
+            self.method = base.method.clone();
+        }
+        for (name, value) in base.query.iter() {
+            self.query
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+        for (name, value) in base.cookies.iter() {
+            self.cookies
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+        for (name, value) in base.headers.iter() {
+            self.headers.entry(name).or_insert_with(|| value.clone());
+        }
+        if self.encode_cookies.is_none() {
+            self.encode_cookies = base.encode_cookies;
+        }
+        if self.auth.is_none() {
+            self.auth = base.auth.clone();
+        }
+        if self.sign.is_none() {
+            self.sign = base.sign.clone();
+        }
+        // Both kinds of body fill the same slot so a template's body is replaced by either
+        if self.body.is_none() && self.body_json.is_none() {
+            self.body = base.body.clone();
+            self.body_json = base.body_json.clone();
+        }
+    }
+
+    pub fn finish<E: serde::de::Error>(self) -> Result<RequestInfo, E> {
+        Ok(RequestInfo {
+            uri: self.uri.ok_or_else(|| E::missing_field("uri"))?,
+            method: self.method.unwrap_or_else(method_get),
+            query: self.query,
+            cookies: self.cookies,
+            headers: self.headers,
+            encode_cookies: self.encode_cookies.unwrap_or(true),
+            auth: self.auth,
+            sign: self.sign,
+            body: self.body.unwrap_or_else(boxed_empty_value),
+            body_json: self.body_json,
+        })
+    }
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -92,44 +601,96 @@ pub struct AlmostRequest {
     method: Method,
     headers: HeaderMap,
     body: String,
+    /// Set if the `Authorization` header has to answer a digest challenge when sending.
+    digest: Option<Credentials>,
+    /// Set if a signature has to be added to the headers when sending.
+    signer: Option<Signer>,
 }
 
 impl RequestInfo {
     #[async_recursion]
-    pub async fn build<C: Connect + Clone + Send + Sync + 'static>(
+    pub async fn build<C>(
         self,
         evaluator: Arc<Evaluator<C>>,
-    ) -> Result<AlmostRequest> {
+        scope: &Scope,
+    ) -> Result<AlmostRequest>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
         let mut headers = HeaderMap::new();
 
         if evaluator.verbose > 0 {
             eprintln!("Building request {} {}", self.method, self.uri);
         }
 
-        {
-            let mut cookie = Cookie::new();
-
-            for (name, value) in self.cookies {
-                cookie.add(
-                    &name,
-                    &match value {
-                        MaybeDeleted::Deleted(_) => continue,
-                        MaybeDeleted::Value(value) => {
-                            if evaluator.verbose > 0 {
-                                eprintln!("Resolving value for cookie {name}");
-                            }
-
-                            value.evaluate(evaluator.clone()).await.with_context(|| {
-                                format!("Failed to resolve value for cookie {name}")
-                            })?
-                        }
-                    },
-                )
+        // The body is evaluated first so that the other parts of the request can refer to it
+        let json = self.body_json.is_some();
+        let body = match self.body_json {
+            Some(json) => {
+                let json = evaluate_json(json, evaluator.clone(), scope)
+                    .await
+                    .context("Failed to resolve value for body_json")?;
+                serde_json::to_string(&json)?
             }
+            None => self
+                .body
+                .evaluate(evaluator.clone(), scope)
+                .await
+                .context("Failed to resolve value for body")?,
+        };
+
+        let uri = self
+            .uri
+            .evaluate_with_body(evaluator.clone(), scope, &body)
+            .await
+            .context("Failed to resolve value for uri")?;
+        // A fragment is never sent so parsing drops it
+        let uri = uri
+            .parse::<Uri>()
+            .with_context(|| format!("{uri} is not a valid uri"))?;
+        if uri.scheme().is_none() || uri.authority().is_none() {
+            bail!("{uri} is not an absolute uri");
+        }
 
-            headers.insert(COOKIE, cookie.into());
+        let mut query = uri.query().map(str::to_string).unwrap_or_default();
+        for (name, value) in self.query {
+            let value = match value {
+                MaybeDeleted::Deleted(_) => continue,
+                MaybeDeleted::Value(value) => value
+                    .evaluate_with_body(evaluator.clone(), scope, &body)
+                    .await
+                    .with_context(|| {
+                        format!("Failed to resolve value for query parameter {name}")
+                    })?,
+            };
+
+            if !query.is_empty() {
+                query.push('&');
+            }
+            query.push_str(&urlencoding::encode(&name));
+            query.push('=');
+            query.push_str(&urlencoding::encode(&value));
         }
 
+        let uri = match query.as_str() {
+            "" => uri,
+            query => {
+                let path_and_query = format!("{}?{query}", uri.path());
+                let mut parts = uri.into_parts();
+                parts.path_and_query = Some(path_and_query.parse()?);
+                Uri::from_parts(parts)?
+            }
+        };
+        let uri = match evaluator.target {
+            Some(ref target) => {
+                let mut parts = uri.into_parts();
+                parts.scheme = target.scheme().cloned();
+                parts.authority = target.authority().cloned();
+                Uri::from_parts(parts)?
+            }
+            None => uri,
+        };
+
         {
             for (name, value) in self
                 .headers
@@ -144,7 +705,7 @@ impl RequestInfo {
                         }
 
                         value
-                            .evaluate(evaluator.clone())
+                            .evaluate_with_body(evaluator.clone(), scope, &body)
                             .await
                             .with_context(|| format!("Failed to resolve value for header {name}"))?
                     }
@@ -156,24 +717,151 @@ impl RequestInfo {
                 headers.insert(name, hval);
             }
         }
+        if json {
+            headers
+                .entry(CONTENT_TYPE)
+                .or_insert(HeaderValue::from_static("application/json"));
+        }
+
+        {
+            // A `Cookie` header set in `headers` is merged with the other cookies, the `cookies`
+            // table overrides the cookies in it
+            let mut cookie = match headers.remove(COOKIE) {
+                Some(header) => header
+                    .to_str()
+                    .map_err(anyhow::Error::from)
+                    .and_then(Cookie::parse)
+                    .context("Value for header cookie is not a valid cookie header")?,
+                None => Cookie::new(),
+            };
+
+            // Configured cookies take precedence over the ones stored in the cookie jar
+            let stored = match evaluator.cookie_jar {
+                Some(ref jar) => jar.matching(scope.task, &uri),
+                None => vec![],
+            };
+            let stored = stored
+                .into_iter()
+                .filter(|(name, _)| !self.cookies.contains_key(name))
+                .collect::<Vec<_>>();
+
+            for (name, value) in self.cookies {
+                let value = match value {
+                    // Also removes a cookie of the same name from the `Cookie` header
+                    MaybeDeleted::Deleted(_) if self.encode_cookies => {
+                        cookie.remove(&urlencoding::encode(&name));
+                        continue;
+                    }
+                    MaybeDeleted::Deleted(_) => {
+                        cookie.remove(&name);
+                        continue;
+                    }
+                    MaybeDeleted::Value(value) => {
+                        if evaluator.verbose > 0 {
+                            eprintln!("Resolving value for cookie {name}");
+                        }
+
+                        value
+                            .evaluate_with_body(evaluator.clone(), scope, &body)
+                            .await
+                            .with_context(|| format!("Failed to resolve value for cookie {name}"))?
+                    }
+                };
+
+                if self.encode_cookies {
+                    cookie.add(&name, &value);
+                } else {
+                    cookie.add_raw(&name, &value);
+                }
+            }
+
+            for (name, value) in stored {
+                if !cookie.contains(&name) {
+                    cookie.add_raw(&name, &value);
+                }
+            }
+
+            let cookie = HeaderValue::try_from(cookie)
+                .context("Cookies do not form a valid header value")?;
+            headers.insert(COOKIE, cookie);
+        }
+
+        // An `Authorization` header set in `headers` takes precedence
+        let mut digest = None;
+        if let Some(auth) = self.auth {
+            match auth
+                .evaluate(evaluator.clone(), scope)
+                .await
+                .context("Failed to resolve value for auth")?
+            {
+                Authorization::Header(value) => {
+                    headers.entry(AUTHORIZATION).or_insert(value);
+                }
+                Authorization::Digest(credentials) if !headers.contains_key(AUTHORIZATION) => {
+                    digest = Some(credentials)
+                }
+                Authorization::Digest(_) => (),
+            }
+        }
+
+        let signer = match self.sign {
+            Some(sign) => Some(
+                sign.evaluate(evaluator.clone(), scope)
+                    .await
+                    .context("Failed to resolve value for sign")?,
+            ),
+            None => None,
+        };
 
         Ok(AlmostRequest {
-            uri: self.uri,
+            uri,
             method: self.method,
             headers,
-            body: self
-                .body
-                .evaluate(evaluator)
-                .await
-                .context("Failed to resolve value for body")?,
+            body,
+            digest,
+            signer,
         })
     }
 }
 
+/// Formats every string inside of `json`, leaving everything else as is.
+#[async_recursion]
+async fn evaluate_json<C>(
+    json: serde_json::Value,
+    evaluator: Arc<Evaluator<C>>,
+    scope: &Scope,
+) -> Result<serde_json::Value>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    use serde_json::Value as Json;
+
+    Ok(match json {
+        Json::String(fmtstr) => {
+            Json::String(Value::Formatted(fmtstr).evaluate(evaluator, scope).await?)
+        }
+        Json::Array(array) => {
+            let mut result = Vec::with_capacity(array.len());
+            for value in array {
+                result.push(evaluate_json(value, evaluator.clone(), scope).await?);
+            }
+            Json::Array(result)
+        }
+        Json::Object(object) => {
+            let mut result = serde_json::Map::with_capacity(object.len());
+            for (key, value) in object {
+                result.insert(key, evaluate_json(value, evaluator.clone(), scope).await?);
+            }
+            Json::Object(result)
+        }
+        other => other,
+    })
+}
+
 // FIXME: This is not really a FIXME since this issue is very hard so solve differently.
 //        Implementing Hash for a HashMap is non-trivial but since this function is called
 //        infrequently so a naive slow solution was chosen.
-#[allow(clippy::derive_hash_xor_eq)]
+#[allow(clippy::derived_hash_with_manual_eq)]
 impl Hash for AlmostRequest {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.uri.hash(state);
@@ -193,6 +881,8 @@ impl Hash for AlmostRequest {
         }
 
         self.body.hash(state);
+        self.digest.hash(state);
+        self.signer.hash(state);
     }
 }
 
@@ -200,19 +890,30 @@ impl From<AlmostRequest> for Request<hyper::Body> {
     fn from(val: AlmostRequest) -> Self {
         let mut request = Request::builder().method(val.method).uri(val.uri);
 
-        *request.headers_mut().unwrap() = val.headers;
+        let headers = request.headers_mut().unwrap();
+        *headers = val.headers;
+        // User-Agent may only appear once, so the default one is only added if neither the
+        // configuration nor a replayed log sets it
+        headers
+            .entry(hyper::header::USER_AGENT)
+            .or_insert_with(|| hyper::http::HeaderValue::from_static(USER_AGENT));
 
-        request
-            .header(
-                hyper::header::USER_AGENT,
-                hyper::http::HeaderValue::from_static(USER_AGENT),
-            )
-            .body(hyper::Body::from(val.body))
-            .unwrap()
+        request.body(hyper::Body::from(val.body)).unwrap()
     }
 }
 
 impl AlmostRequest {
+    pub fn new(method: Method, uri: Uri, headers: HeaderMap, body: String) -> Self {
+        Self {
+            uri,
+            method,
+            headers,
+            body,
+            digest: None,
+            signer: None,
+        }
+    }
+
     pub fn method(&self) -> &Method {
         &self.method
     }
@@ -220,39 +921,37 @@ impl AlmostRequest {
     pub fn uri(&self) -> &Uri {
         &self.uri
     }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn digest(&self) -> Option<&Credentials> {
+        self.digest.as_ref()
+    }
+
+    pub fn signer(&self) -> Option<&Signer> {
+        self.signer.as_ref()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct HammerInfo {
     pub name: String,
+    pub tags: Vec<String>,
     pub request: RequestInfo,
     pub count: u64,
     pub max_concurrency: Option<u64>,
+    pub origin: Origin,
 }
 
-impl<'de> Deserialize<'de> for HammerInfo {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        struct Raw {
-            name: Option<String>,
-            #[serde(flatten)]
-            request: RequestInfo,
-            count: u64,
-            max_concurrency: Option<u64>,
-        }
-
-        let raw = Raw::deserialize(deserializer)?;
-
-        Ok(HammerInfo {
-            name: raw
-                .name
-                .unwrap_or_else(|| format!("{} {}", raw.request.method, raw.request.uri)),
-            request: raw.request,
-            count: raw.count,
-            max_concurrency: raw.max_concurrency,
-        })
-    }
+/// Where a `[[hammer]]` entry was defined, used to point at the source of problems.
+#[derive(Debug, Clone, Default)]
+pub struct Origin {
+    /// The included file the entry is from, `None` for the parsed file itself.
+    pub file: Option<PathBuf>,
+    /// The index of the entry in its file.
+    pub index: usize,
+    /// The templates the entry extends, starting with the one it names.
+    pub templates: Vec<String>,
 }
@@ -0,0 +1,520 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use hyper::client::connect::Connect;
+use serde::{
+    de::{DeserializeSeed, Error, IntoDeserializer, MapAccess, Visitor},
+    Deserialize,
+};
+
+use serde_value::ValueDeserializer;
+
+use super::{
+    auth::OAuth2ClientCredentials,
+    eval::{Evaluator, FromResponseBody, Scope, Value, ValueVisitor},
+    jwt::Jwt,
+    FieldName, FirstFieldName, RequestInfo, TOML_DATETIME,
+};
+
+/// An entry of the top-level `resources` table.
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub source: Source,
+    /// How long the evaluated value can be used before it has to be evaluated again.
+    pub ttl: Option<Duration>,
+    /// Status codes of hammered requests that cause the value to be evaluated again.
+    pub refresh_on: Vec<u16>,
+    pub scope: ResourceScope,
+    /// Makes the resource extract a list of values and use a different one for every request.
+    pub pool: Option<Pool>,
+}
+
+/// Where the value of a resource comes from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Value(Value),
+    OAuth2(OAuth2ClientCredentials),
+    Jwt(Jwt),
+    /// The trimmed contents of a file, `dir` is the directory of the configuration file.
+    File {
+        path: Value,
+        dir: PathBuf,
+    },
+    /// The value of an environment variable.
+    Env(Value),
+    /// The trimmed standard output of a command, which is run in `dir`.
+    Command {
+        args: Vec<Value>,
+        dir: PathBuf,
+    },
+}
+
+/// The kinds of resources that are selected using `type`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResourceType {
+    Oauth2ClientCredentials,
+    Jwt,
+}
+
+/// Which tasks share the value of a resource.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceScope {
+    /// Evaluated once for all tasks.
+    #[default]
+    Global,
+    /// Evaluated separately by every task, which then keeps using its own value.
+    Task,
+    /// Evaluated again for every request.
+    Request,
+}
+
+/// How the value of a pool is picked for every request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pool {
+    RoundRobin,
+    Random,
+}
+
+impl Resource {
+    const FIELDS: &'static [&'static [&'static str]] = &[
+        RequestInfo::FIELDS,
+        FromResponseBody::EXTRA_FIELDS,
+        Options::FIELDS,
+        Resource::SOURCES,
+        OAuth2ClientCredentials::FIELDS,
+        Jwt::FIELDS,
+    ];
+
+    /// Keys that determine where the value of a resource comes from.
+    const SOURCES: &'static [&'static str] = &["type", "file", "env", "command"];
+
+    /// Whether the value of this resource may change while hammering.
+    pub fn is_refreshable(&self) -> bool {
+        self.ttl.is_some()
+            || !self.refresh_on.is_empty()
+            || self.scope == ResourceScope::Request
+            || matches!(self.source, Source::OAuth2(_) | Source::Jwt(_))
+    }
+
+    /// Makes file paths in the resource relative to `dir`.
+    pub fn rebase(&mut self, dir: &Path) {
+        match self.source {
+            Source::Jwt(Jwt {
+                key_file: Some(ref mut path),
+                ..
+            })
+            | Source::File {
+                dir: ref mut path, ..
+            }
+            | Source::Command {
+                dir: ref mut path, ..
+            } => *path = dir.join(&*path),
+            _ => (),
+        }
+    }
+
+    /// Deserializes the rest of a resource table, `first` is a key that has already been read
+    /// from `map` without its value.
+    ///
+    /// `first` is the source of the resource or the first key of its request, it is only `None`
+    /// if the table has neither.
+    fn from_map<'de, A>(
+        mut map: A,
+        first: Option<String>,
+        mut options: Options,
+    ) -> Result<Self, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        fn only_one_source<E: Error>() -> E {
+            E::custom("a resource can only have one of `type`, `file`, `env` or `command`")
+        }
+
+        let source = match first.as_deref() {
+            Some("file") => Some(Source::File {
+                path: map.next_value()?,
+                dir: PathBuf::new(),
+            }),
+            Some("env") => Some(Source::Env(map.next_value()?)),
+            Some("command") => {
+                let args = map.next_value::<Vec<Value>>()?;
+                if args.is_empty() {
+                    return Err(A::Error::custom("`command` must not be empty"));
+                }
+                Some(Source::Command {
+                    args,
+                    dir: PathBuf::new(),
+                })
+            }
+            _ => None,
+        };
+        if let Some(source) = source {
+            while let Some(key) =
+                map.next_key_seed(FieldName(&[Options::FIELDS, Resource::SOURCES]))?
+            {
+                if Resource::SOURCES.contains(&key.as_str()) {
+                    return Err(only_one_source());
+                }
+                options.next_value(&key, &mut map)?;
+            }
+            return Ok(options.finish(source));
+        }
+
+        let mut other = |key: &str, map: &mut A| match key {
+            key if Resource::SOURCES.contains(&key) => Err(only_one_source()),
+            key => options.next_value(key, map),
+        };
+        if first.as_deref() == Some("type") {
+            let source = match map.next_value::<ResourceType>()? {
+                ResourceType::Oauth2ClientCredentials => {
+                    Source::OAuth2(OAuth2ClientCredentials::from_map(
+                        &mut map,
+                        &[
+                            OAuth2ClientCredentials::FIELDS,
+                            Options::FIELDS,
+                            Resource::SOURCES,
+                        ],
+                        &mut other,
+                    )?)
+                }
+                ResourceType::Jwt => Source::Jwt(Jwt::from_map(
+                    &mut map,
+                    &[Jwt::FIELDS, Options::FIELDS, Resource::SOURCES],
+                    &mut other,
+                )?),
+            };
+            return Ok(options.finish(source));
+        }
+
+        let request =
+            FromResponseBody::from_map(map, first, Resource::FIELDS, |key, map| match key {
+                key if Options::FIELDS.contains(&key) => options.next_value(key, map),
+                key if Resource::SOURCES.contains(&key) => Err(A::Error::custom(format_args!(
+                    "`{key}` cannot be used by a resource that sends a request"
+                ))),
+                key => Err(A::Error::custom(format_args!(
+                    "`{key}` can only be used by a resource with a `type`"
+                ))),
+            })?;
+
+        Ok(options.finish(Source::Value(Value::Request(Box::new(request)))))
+    }
+
+    /// Reads the files the resource needs up front.
+    pub fn load(&mut self) -> Result<()> {
+        match self.source {
+            Source::Jwt(ref mut jwt) => jwt.load(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates the resource, which results in more than one value only for pools.
+    ///
+    /// Also returns how long the values can be used, which is the `ttl` of the resource unless
+    /// the values come with their own expiry.
+    pub async fn evaluate<C>(
+        self,
+        evaluator: Arc<Evaluator<C>>,
+        scope: &Scope,
+    ) -> Result<(Vec<String>, Option<Duration>)>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        // Responses are cached, which would make refreshing the value pointless
+        let cached = !self.is_refreshable();
+        let (values, expires_in) = match self.source {
+            Source::Value(Value::Request(req)) if self.pool.is_some() => {
+                (req.resolve_list(evaluator, scope, cached).await?, None)
+            }
+            Source::Value(Value::Request(req)) => {
+                (vec![req.resolve(evaluator, scope, cached).await?], None)
+            }
+            Source::Value(value) => (vec![value.evaluate(evaluator, scope).await?], None),
+            Source::OAuth2(oauth2) => {
+                let (token, expires_in) = oauth2.fetch(evaluator, scope).await?;
+                (vec![token], expires_in)
+            }
+            Source::Jwt(jwt) => {
+                let (token, expires_in) = jwt.mint(evaluator, scope).await?;
+                (vec![token], Some(expires_in))
+            }
+            Source::File { path, dir } => {
+                let path = dir.join(path.evaluate(evaluator, scope).await?);
+                let text = tokio::fs::read_to_string(&path)
+                    .await
+                    .with_context(|| format!("Could not read {}", path.display()))?;
+                (vec![text.trim().to_string()], None)
+            }
+            Source::Env(name) => {
+                let name = name.evaluate(evaluator, scope).await?;
+                match std::env::var(&name) {
+                    Ok(value) => (vec![value], None),
+                    Err(_) => bail!("Environment variable {name} is not set"),
+                }
+            }
+            Source::Command { args, dir } => {
+                let mut evaluated = Vec::with_capacity(args.len());
+                for arg in args {
+                    evaluated.push(arg.evaluate(evaluator.clone(), scope).await?);
+                }
+                (vec![run(&evaluated, &dir).await?], None)
+            }
+        };
+        Ok((values, self.ttl.or(expires_in)))
+    }
+}
+
+impl From<Value> for Resource {
+    fn from(value: Value) -> Self {
+        Options::default().finish(Source::Value(value))
+    }
+}
+
+/// Runs a command and returns its trimmed standard output.
+async fn run(args: &[String], dir: &Path) -> Result<String> {
+    // Commands are checked to not be empty while reading the configuration
+    let (program, args) = args.split_first().unwrap();
+    let mut command = tokio::process::Command::new(program);
+    command.args(args).stdin(std::process::Stdio::null());
+    // The directory of a configuration file in the current directory is empty
+    if !dir.as_os_str().is_empty() {
+        command.current_dir(dir);
+    }
+    let output = command
+        .output()
+        .await
+        .with_context(|| format!("Could not run {program}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| format!("Output of {program} is not valid UTF-8"))?;
+    Ok(stdout.trim().to_string())
+}
+
+impl<'de> Deserialize<'de> for Resource {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ResourceVisitor;
+
+        impl<'de> Visitor<'de> for ResourceVisitor {
+            type Value = Resource;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                ValueVisitor.expecting(f)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                ValueVisitor.visit_str(v).map(Resource::from)
+            }
+
+            fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<Self::Value, E> {
+                ValueVisitor.visit_bool(v).map(Resource::from)
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                ValueVisitor.visit_i64(v).map(Resource::from)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                ValueVisitor.visit_u64(v).map(Resource::from)
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                ValueVisitor.visit_f64(v).map(Resource::from)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                // Which keys are allowed depends on the source of the resource, which can be given
+                // by any of its keys. Only keys of typed resources are buffered until the source
+                // is known, everything else is read straight from `map` so that errors keep
+                // pointing at the key they are about.
+                let mut options = Options::default();
+                let mut buffered = Vec::new();
+                let first = loop {
+                    let Some(key) = map.next_key_seed(FirstFieldName(Resource::FIELDS))? else {
+                        break None;
+                    };
+                    match key.as_str() {
+                        TOML_DATETIME => {
+                            return Ok(Resource::from(Value::Constant(map.next_value()?)))
+                        }
+                        key if Options::FIELDS.contains(&key) => {
+                            options.next_value(key, &mut map)?
+                        }
+                        key if OAuth2ClientCredentials::FIELDS.contains(&key)
+                            || Jwt::FIELDS.contains(&key) =>
+                        {
+                            buffered
+                                .push((key.to_string(), map.next_value::<serde_value::Value>()?))
+                        }
+                        _ => break Some(key),
+                    }
+                };
+
+                Resource::from_map(
+                    Buffered {
+                        entries: buffered.into_iter(),
+                        value: None,
+                        map,
+                    },
+                    first,
+                    options,
+                )
+            }
+        }
+
+        deserializer.deserialize_any(ResourceVisitor)
+    }
+}
+
+/// A [`MapAccess`] that first hands out buffered entries and then the rest of `map`.
+struct Buffered<A> {
+    entries: std::vec::IntoIter<(String, serde_value::Value)>,
+    /// The value of the last buffered key.
+    value: Option<serde_value::Value>,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Buffered<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(ValueDeserializer::new(value)),
+            None => self.map.next_value_seed(seed),
+        }
+    }
+}
+
+/// The properties every resource table can have besides the ones that describe its value.
+#[derive(Default)]
+struct Options {
+    ttl: Option<Duration>,
+    refresh_on: Vec<u16>,
+    scope: ResourceScope,
+    pool: Option<Pool>,
+}
+
+impl Options {
+    const FIELDS: &'static [&'static str] = &["ttl", "refresh_on", "scope", "pool"];
+
+    fn next_value<'de, A: MapAccess<'de>>(
+        &mut self,
+        key: &str,
+        map: &mut A,
+    ) -> Result<(), A::Error> {
+        match key {
+            "ttl" => self.ttl = Some(map.next_value::<WrappedDuration>()?.0),
+            "refresh_on" => self.refresh_on = map.next_value()?,
+            "scope" => self.scope = map.next_value()?,
+            _ => self.pool = Some(map.next_value()?),
+        }
+        Ok(())
+    }
+
+    fn finish(self, source: Source) -> Resource {
+        Resource {
+            source,
+            ttl: self.ttl,
+            refresh_on: self.refresh_on,
+            scope: self.scope,
+            pool: self.pool,
+        }
+    }
+}
+
+/// A non-zero duration written either as a number of seconds or as a string like `4m`, `30s` or
+/// `500ms`.
+pub(super) struct WrappedDuration(pub Duration);
+
+impl<'de> Deserialize<'de> for WrappedDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct DurationVisitor;
+
+        impl<'de> Visitor<'de> for DurationVisitor {
+            type Value = WrappedDuration;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(
+                    f,
+                    "a positive number of seconds or a duration like `4m`, `30s` or `500ms`"
+                )
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                match v {
+                    0 => Err(E::invalid_value(serde::de::Unexpected::Unsigned(v), &self)),
+                    v => Ok(WrappedDuration(Duration::from_secs(v))),
+                }
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                match u64::try_from(v) {
+                    Ok(v) => self.visit_u64(v),
+                    Err(_) => Err(E::invalid_value(serde::de::Unexpected::Signed(v), &self)),
+                }
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                parse_duration(v)
+                    .filter(|duration| !duration.is_zero())
+                    .map(WrappedDuration)
+                    .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(DurationVisitor)
+    }
+}
+
+fn parse_duration(text: &str) -> Option<Duration> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
@@ -28,36 +28,6 @@ pub mod method {
     }
 }
 
-pub mod uri {
-    use hyper::Uri;
-    use serde::{
-        de::{Error, Unexpected},
-        Deserializer,
-    };
-
-    pub fn deserialize<'de, D>(de: D) -> Result<Uri, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        struct Visitor;
-
-        impl<'de> serde::de::Visitor<'de> for Visitor {
-            type Value = Uri;
-
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(formatter, "a valid uri")
-            }
-
-            fn visit_str<E: Error>(self, val: &str) -> Result<Self::Value, E> {
-                val.parse()
-                    .map_err(|_| Error::invalid_value(Unexpected::Str(val), &self))
-            }
-        }
-
-        de.deserialize_str(Visitor)
-    }
-}
-
 pub mod header_name {
     use hyper::header::HeaderName;
     use serde::{
@@ -89,7 +59,7 @@ pub mod header_name {
 }
 
 pub mod generic_header_map {
-    use hyper::{HeaderMap, header::HeaderName};
+    use hyper::{header::HeaderName, HeaderMap};
     use serde::{de::MapAccess, Deserialize, Deserializer};
 
     pub fn deserialize<'de, V: Deserialize<'de> + 'de, D>(de: D) -> Result<HeaderMap<V>, D::Error>
@@ -0,0 +1,568 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use hyper::{
+    client::connect::Connect,
+    header::{HeaderName, AUTHORIZATION, COOKIE, HOST},
+    http::HeaderValue,
+    HeaderMap, Method, Uri,
+};
+use serde::{de::Error, Deserialize, Deserializer};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use super::{
+    eval::{Evaluator, Scope, Value},
+    format::{self, Expression, Segment},
+    serde_http,
+};
+
+/// The `sign` table of a request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Sign {
+    Hmac {
+        key: Value,
+        #[serde(default)]
+        algorithm: HmacAlgorithm,
+        /// The string that is signed, see [`Part`] for what it can contain.
+        #[serde(deserialize_with = "canonical")]
+        canonical: String,
+        #[serde(
+            default = "default_header",
+            deserialize_with = "serde_http::header_name::deserialize"
+        )]
+        header: HeaderName,
+        /// The value of `header`, which can contain `${signature}` too.
+        #[serde(default = "default_value", deserialize_with = "header_value")]
+        value: String,
+        #[serde(default)]
+        encoding: Encoding,
+        /// A header that is set to the current time before signing.
+        #[serde(default, deserialize_with = "optional_header_name")]
+        timestamp_header: Option<HeaderName>,
+    },
+    AwsSigv4 {
+        access_key: Value,
+        secret_key: Value,
+        session_token: Option<Value>,
+        region: Value,
+        #[serde(default = "default_service")]
+        service: Value,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// How the bytes of a signature are turned into a string.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+/// A `sign` table whose values have been evaluated, the signature itself can only be computed
+/// when sending the request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Signer {
+    Hmac {
+        key: String,
+        algorithm: HmacAlgorithm,
+        canonical: String,
+        header: HeaderName,
+        value: String,
+        encoding: Encoding,
+        timestamp_header: Option<HeaderName>,
+    },
+    AwsSigv4 {
+        access_key: String,
+        secret_key: String,
+        session_token: Option<String>,
+        region: String,
+        service: String,
+    },
+}
+
+impl Sign {
+    pub async fn evaluate<C>(self, evaluator: Arc<Evaluator<C>>, scope: &Scope) -> Result<Signer>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        Ok(match self {
+            Sign::Hmac {
+                key,
+                algorithm,
+                canonical,
+                header,
+                value,
+                encoding,
+                timestamp_header,
+            } => Signer::Hmac {
+                key: key.evaluate(evaluator, scope).await?,
+                algorithm,
+                canonical,
+                header,
+                value,
+                encoding,
+                timestamp_header,
+            },
+            Sign::AwsSigv4 {
+                access_key,
+                secret_key,
+                session_token,
+                region,
+                service,
+            } => Signer::AwsSigv4 {
+                access_key: access_key.evaluate(evaluator.clone(), scope).await?,
+                secret_key: secret_key.evaluate(evaluator.clone(), scope).await?,
+                session_token: match session_token {
+                    Some(token) => Some(token.evaluate(evaluator.clone(), scope).await?),
+                    None => None,
+                },
+                region: region.evaluate(evaluator.clone(), scope).await?,
+                service: service.evaluate(evaluator, scope).await?,
+            },
+        })
+    }
+}
+
+impl Signer {
+    /// Adds the signature of a request that is sent at `now` to its headers.
+    pub fn sign(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        body: &str,
+        now: SystemTime,
+    ) -> Result<()> {
+        match self {
+            Signer::Hmac {
+                key,
+                algorithm,
+                canonical,
+                header,
+                value,
+                encoding,
+                timestamp_header,
+            } => {
+                let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                if let Some(name) = timestamp_header {
+                    headers.insert(name, HeaderValue::from(timestamp));
+                }
+
+                let mut message = Message {
+                    method,
+                    uri,
+                    headers,
+                    body,
+                    timestamp,
+                    signature: None,
+                };
+                let canonical = message.render(canonical)?;
+
+                let signature = match algorithm {
+                    HmacAlgorithm::Sha256 => mac::<Hmac<Sha256>>(key.as_bytes(), &canonical),
+                    HmacAlgorithm::Sha384 => mac::<Hmac<Sha384>>(key.as_bytes(), &canonical),
+                    HmacAlgorithm::Sha512 => mac::<Hmac<Sha512>>(key.as_bytes(), &canonical),
+                };
+                let signature = match encoding {
+                    Encoding::Hex => hex(&signature),
+                    Encoding::Base64 => base64::engine::general_purpose::STANDARD.encode(signature),
+                };
+
+                message.signature = Some(&signature);
+                let value = message.render(value)?;
+                let value = HeaderValue::try_from(value)
+                    .with_context(|| format!("Value of the {header} header is not valid"))?;
+                headers.insert(header, value);
+            }
+            Signer::AwsSigv4 {
+                access_key,
+                secret_key,
+                session_token,
+                region,
+                service,
+            } => {
+                let date_time = amz_date(now);
+                let date = &date_time[..8];
+                let payload = hex(&Sha256::digest(body));
+
+                headers.remove(AUTHORIZATION);
+                headers.insert(HOST, HeaderValue::try_from(host(uri))?);
+                headers.insert("x-amz-date", HeaderValue::try_from(date_time.as_str())?);
+                // Only S3 requires the hash of the payload as a header
+                if service == "s3" {
+                    headers.insert("x-amz-content-sha256", HeaderValue::try_from(&payload)?);
+                }
+                if let Some(token) = session_token {
+                    headers.insert(
+                        "x-amz-security-token",
+                        HeaderValue::try_from(token)
+                            .context("Session token is not a valid header value")?,
+                    );
+                }
+
+                // Requests without cookies are sent with an empty `Cookie` header, which proxies
+                // may strip
+                let mut names = headers
+                    .keys()
+                    .filter(|&name| {
+                        name != COOKIE || headers.get_all(name).iter().any(|v| !v.is_empty())
+                    })
+                    .map(HeaderName::as_str)
+                    .collect::<Vec<_>>();
+                names.sort_unstable();
+                // Names are repeated for every value
+                names.dedup();
+                let signed_headers = names.join(";");
+                let canonical_headers = names
+                    .iter()
+                    .map(|name| {
+                        let values = headers
+                            .get_all(*name)
+                            .iter()
+                            .map(|value| {
+                                let value = String::from_utf8_lossy(value.as_bytes());
+                                value.split_whitespace().collect::<Vec<_>>().join(" ")
+                            })
+                            .collect::<Vec<_>>();
+                        format!("{name}:{}\n", values.join(","))
+                    })
+                    .collect::<String>();
+
+                let canonical_request = format!(
+                    "{method}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{payload}",
+                    canonical_path(uri.path(), service == "s3"),
+                    canonical_query(uri.query().unwrap_or_default()),
+                );
+
+                let scope = format!("{date}/{region}/{service}/aws4_request");
+                let string_to_sign = format!(
+                    "AWS4-HMAC-SHA256\n{date_time}\n{scope}\n{}",
+                    hex(&Sha256::digest(canonical_request))
+                );
+
+                let key = [date, region, service, "aws4_request"]
+                    .iter()
+                    .fold(format!("AWS4{secret_key}").into_bytes(), |key, data| {
+                        mac::<Hmac<Sha256>>(&key, data)
+                    });
+                let signature = hex(&mac::<Hmac<Sha256>>(&key, &string_to_sign));
+
+                let authorization = format!(
+                    "AWS4-HMAC-SHA256 Credential={access_key}/{scope}, \
+                     SignedHeaders={signed_headers}, Signature={signature}"
+                );
+                headers.insert(
+                    AUTHORIZATION,
+                    HeaderValue::try_from(authorization)
+                        .context("Access key is not a valid header value")?,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A value that can be used in the `canonical` string and `value` of an HMAC signature.
+enum Part<'a> {
+    Method,
+    /// The path of the uri.
+    Path,
+    /// The query of the uri without the leading `?`.
+    Query,
+    Body,
+    /// The number of seconds since the unix epoch.
+    Timestamp,
+    Header(&'a str),
+    Signature,
+}
+
+impl<'a> Part<'a> {
+    fn parse(reference: &'a str) -> Result<Self> {
+        Ok(match reference {
+            "method" => Part::Method,
+            "path" => Part::Path,
+            "query" => Part::Query,
+            "body" => Part::Body,
+            "timestamp" => Part::Timestamp,
+            "signature" => Part::Signature,
+            reference => match reference.strip_prefix("headers.") {
+                Some(name) => Part::Header(name),
+                None => bail!(
+                    "Unknown reference {reference}, expected one of method, path, query, body, \
+                     timestamp, signature or headers.<name>"
+                ),
+            },
+        })
+    }
+}
+
+/// The parts of a request that are being signed.
+struct Message<'a> {
+    method: &'a Method,
+    uri: &'a Uri,
+    headers: &'a HeaderMap,
+    body: &'a str,
+    timestamp: u64,
+    /// Only known once the canonical string has been signed.
+    signature: Option<&'a str>,
+}
+
+impl<'a> Message<'a> {
+    fn render(&self, template: &str) -> Result<String> {
+        let mut out = String::with_capacity(template.len());
+        for segment in format::parse(template)? {
+            match segment {
+                Segment::Literal(literal) => out.push_str(&literal),
+                Segment::Spec(spec) => {
+                    let expression = Expression::parse(&spec)?;
+                    let value = self.lookup(Part::parse(expression.reference)?);
+                    out.push_str(&expression.apply(value)?);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// An error means that the value is missing.
+    fn lookup(&self, part: Part) -> Result<String> {
+        Ok(match part {
+            Part::Method => self.method.to_string(),
+            Part::Path => self.uri.path().to_string(),
+            Part::Query => self.uri.query().unwrap_or_default().to_string(),
+            Part::Body => self.body.to_string(),
+            Part::Timestamp => self.timestamp.to_string(),
+            Part::Header(name) => self
+                .headers
+                .get(name)
+                .with_context(|| format!("Request does not have a {name} header"))?
+                .to_str()
+                .with_context(|| format!("Value of the {name} header is not a string"))?
+                .to_string(),
+            Part::Signature => self
+                .signature
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("signature can only be used in the value of the header"))?,
+        })
+    }
+}
+
+fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The value of the `Host` header that is sent for `uri`.
+fn host(uri: &Uri) -> String {
+    let host = uri.host().unwrap_or_default();
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    };
+    match uri.port_u16() {
+        Some(port) if port != default_port => format!("{host}:{port}"),
+        _ => host.to_string(),
+    }
+}
+
+/// Percent encodes everything except unreserved characters the way AWS expects it.
+fn aws_encode(text: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            b'/' if !encode_slash => out.push('/'),
+            byte => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+fn decode(text: &str) -> String {
+    String::from_utf8_lossy(&urlencoding::decode_binary(text.as_bytes())).into_owned()
+}
+
+/// S3 expects the path to be encoded once, every other service expects the path as it is sent
+/// to be encoded a second time, which keeps an encoded `/` apart from a real one.
+fn canonical_path(path: &str, s3: bool) -> String {
+    match s3 {
+        true => aws_encode(&decode(path), false),
+        false => aws_encode(path, false),
+    }
+}
+
+fn canonical_query(query: &str) -> String {
+    let mut params = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            (
+                aws_encode(&decode(name), true),
+                aws_encode(&decode(value), true),
+            )
+        })
+        .collect::<Vec<_>>();
+    params.sort_unstable();
+    params
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Formats `time` like `20150830T123600Z`.
+fn amz_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // Converts the days since the epoch into a date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Checks that every reference in a template is known, `signature` only if `signature` is true.
+fn check_template(template: &str, signature: bool) -> Result<()> {
+    for segment in format::parse(template)? {
+        if let Segment::Spec(spec) = segment {
+            let expression = Expression::parse(&spec)?;
+            if let Part::Signature = Part::parse(expression.reference)? {
+                if !signature {
+                    bail!("signature can only be used in the value of the header");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn canonical<'de, D: Deserializer<'de>>(de: D) -> Result<String, D::Error> {
+    let template = String::deserialize(de)?;
+    check_template(&template, false).map_err(|e| D::Error::custom(format_args!("{e:#}")))?;
+    Ok(template)
+}
+
+fn header_value<'de, D: Deserializer<'de>>(de: D) -> Result<String, D::Error> {
+    let template = String::deserialize(de)?;
+    check_template(&template, true).map_err(|e| D::Error::custom(format_args!("{e:#}")))?;
+    Ok(template)
+}
+
+fn optional_header_name<'de, D: Deserializer<'de>>(de: D) -> Result<Option<HeaderName>, D::Error> {
+    serde_http::header_name::deserialize(de).map(Some)
+}
+
+fn default_header() -> HeaderName {
+    HeaderName::from_static("x-signature")
+}
+
+fn default_value() -> String {
+    "${signature}".to_string()
+}
+
+fn default_service() -> Value {
+    Value::Formatted("s3".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// `2015-08-30T12:36:00Z`, the time used by the AWS Signature Version 4 test suite.
+    const TEST_SUITE_TIME: u64 = 1440938160;
+
+    fn sign_aws(path: &str, mut headers: HeaderMap) -> String {
+        let signer = Signer::AwsSigv4 {
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+            region: "us-east-1".to_string(),
+            service: "service".to_string(),
+        };
+        let uri = format!("https://example.amazonaws.com{path}")
+            .parse()
+            .unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(TEST_SUITE_TIME);
+        signer
+            .sign(&Method::GET, &uri, &mut headers, "", now)
+            .unwrap();
+
+        assert_eq!(headers[HOST], "example.amazonaws.com");
+        assert_eq!(headers["x-amz-date"], "20150830T123600Z");
+        headers[AUTHORIZATION].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_aws_sigv4() {
+        // get-vanilla of the test suite
+        assert_eq!(
+            sign_aws("/", HeaderMap::new()),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+
+        // get-vanilla-query-order-key-case
+        assert_eq!(
+            sign_aws("/?Param2=value2&Param1=value1", HeaderMap::new()),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+        );
+
+        // get-vanilla-empty-query-key
+        assert_eq!(
+            sign_aws("/?Param1=value1", HeaderMap::new()),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=a67d582fa61cc504c4bae71f336f98b97f1ea3c7a6bfe1b6e45aec72011b9aeb"
+        );
+
+        // The empty `Cookie` header of requests without cookies is not signed
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static(""));
+        assert_eq!(sign_aws("/", headers), sign_aws("/", HeaderMap::new()));
+    }
+}
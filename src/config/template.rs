@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize,
+};
+
+use super::{HammerInfo, Origin, PartialRequest, RequestInfo};
+
+/// A `[[hammer]]` entry or a `[templates.NAME]` table before templates have been applied.
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+    pub extends: Option<String>,
+    pub request: PartialRequest,
+    pub name: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub count: Option<u64>,
+    pub max_concurrency: Option<u64>,
+}
+
+impl Template {
+    const FIELDS: &'static [&'static [&'static str]] = &[
+        RequestInfo::FIELDS,
+        &["extends", "name", "tags", "count", "max_concurrency"],
+    ];
+
+    fn from_map<'de, A>(map: A) -> Result<Self, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut extends = None;
+        let mut name = None;
+        let mut tags = None;
+        let mut count = None;
+        let mut max_concurrency = None;
+
+        let request = PartialRequest::from_map(map, None, Self::FIELDS, |key, map| {
+            match key {
+                "extends" => extends = Some(map.next_value()?),
+                "name" => name = Some(map.next_value()?),
+                "tags" => tags = Some(map.next_value()?),
+                "count" => count = Some(map.next_value()?),
+                _ => max_concurrency = Some(map.next_value()?),
+            }
+            Ok(())
+        })?;
+
+        Ok(Template {
+            extends,
+            request,
+            name,
+            tags,
+            count,
+            max_concurrency,
+        })
+    }
+
+    /// Fills in everything that is not set in this template from `base`.
+    fn merge(&mut self, base: &Template) {
+        self.request.merge(&base.request);
+        if self.name.is_none() {
+            self.name = base.name.clone();
+        }
+        if self.tags.is_none() {
+            self.tags = base.tags.clone();
+        }
+        if self.count.is_none() {
+            self.count = base.count;
+        }
+        if self.max_concurrency.is_none() {
+            self.max_concurrency = base.max_concurrency;
+        }
+    }
+
+    /// Applies the template this one extends (and the one that one extends and so on), returns
+    /// the names of the applied templates.
+    pub fn apply(&mut self, templates: &HashMap<String, Template>) -> Result<Vec<String>> {
+        let mut seen = vec![];
+        while let Some(name) = self.extends.take() {
+            if seen.contains(&name) {
+                seen.push(name);
+                bail!(
+                    "Templates extend each other in a cycle: {}",
+                    seen.join(" -> ")
+                );
+            }
+
+            let base = templates
+                .get(&name)
+                .with_context(|| format!("Template {name} does not exist"))?;
+            self.merge(base);
+            self.extends = base.extends.clone();
+            seen.push(name);
+        }
+        Ok(seen)
+    }
+
+    pub fn finish<E: serde::de::Error>(self) -> Result<HammerInfo, E> {
+        let request = self.request.finish()?;
+        Ok(HammerInfo {
+            name: self
+                .name
+                .unwrap_or_else(|| format!("{} {}", request.method, request.uri)),
+            tags: self.tags.unwrap_or_default(),
+            count: self.count.ok_or_else(|| E::missing_field("count"))?,
+            max_concurrency: self.max_concurrency,
+            request,
+            origin: Origin::default(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct TemplateVisitor;
+
+        impl<'de> Visitor<'de> for TemplateVisitor {
+            type Value = Template;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a template table")
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                Template::from_map(map)
+            }
+        }
+
+        deserializer.deserialize_map(TemplateVisitor)
+    }
+}
+
+/// A `[[hammer]]` entry, unlike a template it must be complete unless it extends a template.
+#[derive(Debug, Clone)]
+pub struct Entry(pub Template);
+
+impl<'de> Deserialize<'de> for Entry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct EntryVisitor;
+
+        impl<'de> Visitor<'de> for EntryVisitor {
+            type Value = Entry;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a hammer table")
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let template = Template::from_map(map)?;
+                // Report missing fields right away so that the error points at this entry
+                if template.extends.is_none() {
+                    if template.request.uri.is_none() {
+                        return Err(serde::de::Error::missing_field("uri"));
+                    }
+                    if template.count.is_none() {
+                        return Err(serde::de::Error::missing_field("count"));
+                    }
+                }
+                Ok(Entry(template))
+            }
+        }
+
+        deserializer.deserialize_map(EntryVisitor)
+    }
+}
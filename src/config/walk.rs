@@ -0,0 +1,192 @@
+use std::fmt::Display;
+
+use super::{
+    auth::Auth, eval::MaybeDeleted, resource::Source, sign::Sign, HammerFile, RequestInfo,
+};
+
+/// A single step in a [`Location`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Name(String),
+    Index(usize),
+}
+
+/// A location inside of a configuration file, like `hammer[1].headers.Authorization`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Location(pub Vec<Key>);
+
+impl Location {
+    pub fn join(&self, key: impl Into<String>) -> Self {
+        let mut new = self.clone();
+        new.0.push(Key::Name(key.into()));
+        new
+    }
+
+    pub fn index(&self, index: usize) -> Self {
+        let mut new = self.clone();
+        new.0.push(Key::Index(index));
+        new
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, key) in self.0.iter().enumerate() {
+            match key {
+                Key::Name(name) if i == 0 => write!(f, "{name}")?,
+                Key::Name(name) => write!(f, ".{name}")?,
+                Key::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A string in the configuration that is interpreted by `http-hammer`.
+#[derive(Debug, Clone, Copy)]
+pub enum Field<'a> {
+    /// A string that may contain `${...}` interpolations.
+    Formatted(&'a str),
+    /// Like [`Field::Formatted`] but part of a request other than its body, so `${body}` refers
+    /// to the body of the request.
+    RequestPart(&'a str),
+    /// Like [`Field::RequestPart`] but the result will be used as a header value.
+    Header(&'a str),
+    /// Like [`Field::RequestPart`] but the result will be used as the uri of a request.
+    Uri(&'a str),
+    /// A value that is used as it is, like a number, along with the kind of field it is used as.
+    Constant(&'a str, fn(&'a str) -> Field<'a>),
+    /// The `format` of a resource table, which should contain a `{}`.
+    ResponseFormat(&'a str),
+}
+
+impl HammerFile {
+    /// Calls `f` for every string that will be interpreted when evaluating the configuration.
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(Location, Field<'a>)) {
+        let root = Location::default();
+
+        for (name, resource) in self.resources.iter() {
+            let location = root.join("resources").join(name);
+            match resource.source {
+                Source::Value(ref value) => value.walk(location, f),
+                Source::OAuth2(ref oauth2) => {
+                    oauth2.token_url.walk(location.join("token_url"), f);
+                    oauth2.client_id.walk(location.join("client_id"), f);
+                    oauth2.client_secret.walk(location.join("client_secret"), f);
+                    if let Some(ref scopes) = oauth2.scopes {
+                        scopes.walk(location.join("scopes"), f);
+                    }
+                }
+                Source::Jwt(ref jwt) => {
+                    if let Some(ref secret) = jwt.secret {
+                        secret.walk(location.join("secret"), f);
+                    }
+                    if let Some(ref kid) = jwt.kid {
+                        kid.walk(location.join("kid"), f);
+                    }
+                    walk_json(&jwt.claims, location.join("claims"), f);
+                }
+                Source::File { ref path, .. } => path.walk(location.join("file"), f),
+                Source::Env(ref name) => name.walk(location.join("env"), f),
+                Source::Command { ref args, .. } => {
+                    for (i, arg) in args.iter().enumerate() {
+                        arg.walk(location.join("command").index(i), f);
+                    }
+                }
+            }
+        }
+
+        for (i, hammer) in self.hammer.iter().enumerate() {
+            hammer.request.walk(root.join("hammer").index(i), f);
+        }
+    }
+}
+
+impl RequestInfo {
+    pub fn walk<'a>(&'a self, location: Location, f: &mut impl FnMut(Location, Field<'a>)) {
+        self.uri.walk_with(location.join("uri"), f, Field::Uri);
+
+        for (name, value) in self.query.iter() {
+            if let MaybeDeleted::Value(value) = value {
+                value.walk_with(location.join("query").join(name), f, Field::RequestPart);
+            }
+        }
+
+        for (name, value) in self.cookies.iter() {
+            if let MaybeDeleted::Value(value) = value {
+                value.walk_with(location.join("cookies").join(name), f, Field::RequestPart);
+            }
+        }
+
+        for (name, value) in self.headers.iter() {
+            if let MaybeDeleted::Value(value) = value {
+                let location = location.join("headers").join(name.as_str());
+                value.walk_with(location, f, Field::Header);
+            }
+        }
+
+        match self.auth {
+            Some(Auth::Basic {
+                ref user,
+                ref password,
+            })
+            | Some(Auth::Digest {
+                ref user,
+                ref password,
+            }) => {
+                user.walk(location.join("auth").join("user"), f);
+                password.walk(location.join("auth").join("password"), f);
+            }
+            Some(Auth::Bearer { ref token }) => token.walk(location.join("auth").join("token"), f),
+            None => (),
+        }
+
+        match self.sign {
+            Some(Sign::Hmac { ref key, .. }) => key.walk(location.join("sign").join("key"), f),
+            Some(Sign::AwsSigv4 {
+                ref access_key,
+                ref secret_key,
+                ref session_token,
+                ref region,
+                ref service,
+            }) => {
+                let location = location.join("sign");
+                access_key.walk(location.join("access_key"), f);
+                secret_key.walk(location.join("secret_key"), f);
+                if let Some(token) = session_token {
+                    token.walk(location.join("session_token"), f);
+                }
+                region.walk(location.join("region"), f);
+                service.walk(location.join("service"), f);
+            }
+            None => (),
+        }
+
+        self.body.walk(location.join("body"), f);
+
+        if let Some(ref json) = self.body_json {
+            walk_json(json, location.join("body_json"), f);
+        }
+    }
+}
+
+fn walk_json<'a>(
+    json: &'a serde_json::Value,
+    location: Location,
+    f: &mut impl FnMut(Location, Field<'a>),
+) {
+    match json {
+        serde_json::Value::String(fmtstr) => f(location, Field::Formatted(fmtstr)),
+        serde_json::Value::Array(array) => {
+            for (i, value) in array.iter().enumerate() {
+                walk_json(value, location.index(i), f);
+            }
+        }
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter() {
+                walk_json(value, location.join(key), f);
+            }
+        }
+        _ => (),
+    }
+}
@@ -1,33 +1,105 @@
-use std::fmt::Write;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use hyper::http;
+use anyhow::{bail, Context, Result};
+use hyper::{header::SET_COOKIE, http, HeaderMap, Uri};
+use serde::Deserialize;
 
-#[derive(Debug, Clone, Default)]
-pub struct Cookie(String);
+/// The value of a `Cookie` header.
+///
+/// Every name appears at most once and cookies are kept in the order they were first added in, so
+/// the same cookies always result in the same header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cookie(Vec<(String, String)>);
 
 impl Cookie {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Parses the value of a `Cookie` header, if a name appears multiple times only the first
+    /// cookie with that name is kept.
+    pub fn parse(header: &str) -> Result<Self> {
+        let mut cookie = Self::new();
+        for pair in header.split(';').map(str::trim).filter(|x| !x.is_empty()) {
+            let (name, value) = pair
+                .split_once('=')
+                .with_context(|| format!("Cookie {pair} is not of the form name=value"))?;
+            let name = name.trim();
+            if name.is_empty() {
+                bail!("Cookie {pair} does not have a name");
+            }
+            if !cookie.contains(name) {
+                cookie.0.push((name.to_string(), value.trim().to_string()));
+            }
+        }
+        Ok(cookie)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Returns the value of a cookie as it is sent, so possibly still percent encoded.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Adds a cookie after percent encoding its name and value, replacing any cookie with the
+    /// same name.
     pub fn add(&mut self, name: &str, value: &str) {
-        if !self.0.is_empty() {
-            self.0.push_str("; ");
+        self.add_raw(&urlencoding::encode(name), &urlencoding::encode(value));
+    }
+
+    /// Adds a cookie without encoding it, for values that are already encoded like the ones
+    /// received in a `Set-Cookie` header.
+    pub fn add_raw(&mut self, name: &str, value: &str) {
+        match self.0.iter_mut().find(|(n, _)| n == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.0.push((name.to_string(), value.to_string())),
         }
+    }
 
-        write!(
-            self.0,
-            "{}={}",
-            urlencoding::encode(name).as_ref(),
-            urlencoding::encode(value).as_ref()
-        )
-        .unwrap();
+    /// Removes the cookie with a name as it is sent.
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(n, _)| n != name);
     }
 }
 
-impl Into<http::HeaderValue> for Cookie {
-    fn into(self) -> http::HeaderValue {
-        self.0.try_into().unwrap()
+impl std::fmt::Display for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<Cookie> for http::HeaderValue {
+    type Error = http::header::InvalidHeaderValue;
+
+    fn try_from(val: Cookie) -> Result<Self, Self::Error> {
+        val.to_string().try_into()
     }
 }
 
@@ -46,3 +118,234 @@ impl<'a, A: Into<&'a str>> FromIterator<(A, A)> for Cookie {
         n
     }
 }
+
+/// Which tasks share the cookies stored in the cookie jar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JarScope {
+    /// All tasks send the same cookies.
+    Global,
+    /// Every task has its own cookies, like separate browsers.
+    Task,
+}
+
+/// The cookie jars used while hammering, either a single one or one for every task.
+#[derive(Debug)]
+pub struct CookieJars {
+    scope: JarScope,
+    jars: Mutex<HashMap<usize, CookieJar>>,
+}
+
+impl CookieJars {
+    pub fn new(scope: JarScope) -> Self {
+        Self {
+            scope,
+            jars: Default::default(),
+        }
+    }
+
+    fn with<T>(&self, task: usize, f: impl FnOnce(&mut CookieJar) -> T) -> T {
+        let task = match self.scope {
+            JarScope::Global => 0,
+            JarScope::Task => task,
+        };
+        f(self.jars.lock().unwrap().entry(task).or_default())
+    }
+
+    /// Stores the cookies set by a response to a request for `uri` sent by `task`.
+    pub fn store(&self, task: usize, uri: &Uri, headers: &HeaderMap) {
+        self.with(task, |jar| jar.store(uri, headers))
+    }
+
+    /// Returns the cookies `task` should send with a request for `uri`.
+    pub fn matching(&self, task: usize, uri: &Uri) -> Vec<(String, String)> {
+        self.with(task, |jar| jar.matching(uri))
+    }
+}
+
+/// Stores the cookies set by responses and returns the ones that should be sent with a request,
+/// following the rules of RFC 6265.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Vec<StoredCookie>,
+}
+
+impl CookieJar {
+    /// Stores the cookies from every `Set-Cookie` header of a response to a request for `uri`.
+    pub fn store(&mut self, uri: &Uri, headers: &HeaderMap) {
+        let Some(host) = uri.host().map(str::to_ascii_lowercase) else {
+            return;
+        };
+
+        let now = SystemTime::now();
+        for header in headers.get_all(SET_COOKIE) {
+            let Some(cookie) = header
+                .to_str()
+                .ok()
+                .and_then(|header| StoredCookie::parse(header, &host, uri.path(), now))
+            else {
+                continue;
+            };
+
+            // A cookie replaces an older one with the same name, domain and path, expired
+            // cookies only remove the older one
+            self.cookies.retain(|c| {
+                c.name != cookie.name || c.domain != cookie.domain || c.path != cookie.path
+            });
+            if !cookie.is_expired(now) {
+                self.cookies.push(cookie);
+            }
+        }
+    }
+
+    /// Returns the name and value of every cookie that should be sent with a request for `uri`,
+    /// cookies with longer paths come first and otherwise the oldest cookies come first.
+    pub fn matching(&mut self, uri: &Uri) -> Vec<(String, String)> {
+        let now = SystemTime::now();
+        self.cookies.retain(|c| !c.is_expired(now));
+
+        let Some(host) = uri.host().map(str::to_ascii_lowercase) else {
+            return vec![];
+        };
+        let secure = uri.scheme_str() == Some("https");
+
+        let mut matching = self
+            .cookies
+            .iter()
+            .filter(|c| {
+                (secure || !c.secure)
+                    && path_matches(uri.path(), &c.path)
+                    && match c.host_only {
+                        true => host == c.domain,
+                        false => domain_matches(&host, &c.domain),
+                    }
+            })
+            .collect::<Vec<_>>();
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+
+        matching
+            .into_iter()
+            .map(|c| (c.name.clone(), c.value.clone()))
+            .collect()
+    }
+}
+
+/// A cookie received in a `Set-Cookie` header.
+#[derive(Debug, Clone)]
+struct StoredCookie {
+    name: String,
+    value: String,
+    domain: String,
+    /// Set if the response did not have a `Domain` attribute, the cookie is then only sent to
+    /// exactly `domain` and not its subdomains.
+    host_only: bool,
+    path: String,
+    expires: Option<SystemTime>,
+    secure: bool,
+}
+
+impl StoredCookie {
+    /// Parses a `Set-Cookie` header of a response to a request for `path` on `host`, returns
+    /// `None` if the cookie is invalid or may not be set by `host`.
+    fn parse(header: &str, host: &str, path: &str, now: SystemTime) -> Option<Self> {
+        let mut attributes = header.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.to_string(),
+            host_only: true,
+            path: default_path(path).to_string(),
+            expires: None,
+            secure: false,
+        };
+
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "expires" => cookie.expires = httpdate::parse_http_date(value).ok(),
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if domain.is_empty() {
+                        continue;
+                    }
+                    if !domain_matches(host, &domain) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                _ => (),
+            }
+        }
+
+        // Max-Age takes precedence over Expires
+        if let Some(max_age) = max_age {
+            cookie.expires = match u64::try_from(max_age) {
+                // A Max-Age too far in the future to be represented never expires
+                Ok(seconds) if seconds > 0 => now.checked_add(Duration::from_secs(seconds)),
+                _ => Some(UNIX_EPOCH),
+            };
+        }
+
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// The directory of the request path, used as the path of cookies without a `Path` attribute.
+fn default_path(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(end) => &path[..end],
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.parse::<IpAddr>().is_err()
+            && host
+                .strip_suffix(domain)
+                .is_some_and(|rest| rest.ends_with('.')))
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    match path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_age_overflow() {
+        let now = SystemTime::now();
+        let cookie =
+            StoredCookie::parse("a=b; Max-Age=9223372036854775807", "example.com", "/", now)
+                .unwrap();
+        assert_eq!(cookie.expires, None);
+        assert!(!cookie.is_expired(now));
+
+        let cookie = StoredCookie::parse("a=b; Max-Age=60", "example.com", "/", now).unwrap();
+        assert_eq!(cookie.expires, Some(now + Duration::from_secs(60)));
+
+        let cookie = StoredCookie::parse("a=b; Max-Age=0", "example.com", "/", now).unwrap();
+        assert!(cookie.is_expired(now));
+    }
+}
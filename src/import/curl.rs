@@ -0,0 +1,323 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+
+use super::{escape, ImportedEntry, ImportedFile};
+use crate::cli::ImportOptions;
+
+/// Flags that do not take a value and do not affect the request.
+const IGNORED_FLAGS: &[&str] = &[
+    "-s",
+    "--silent",
+    "-S",
+    "--show-error",
+    "-k",
+    "--insecure",
+    "-L",
+    "--location",
+    "-v",
+    "--verbose",
+    "-i",
+    "--include",
+    "-f",
+    "--fail",
+    "--http1.0",
+    "--http1.1",
+    "--http2",
+    "--http2-prior-knowledge",
+    "--http3",
+    "-#",
+    "--progress-bar",
+    "-N",
+    "--no-buffer",
+    "-g",
+    "--globoff",
+];
+
+/// Flags that take a value which does not affect the request.
+const IGNORED_VALUE_FLAGS: &[&str] = &[
+    "-o",
+    "--output",
+    "-m",
+    "--max-time",
+    "--connect-timeout",
+    "-x",
+    "--proxy",
+    "-w",
+    "--write-out",
+    "--retry",
+    "-c",
+    "--cookie-jar",
+    "--cacert",
+    "--cert",
+    "--key",
+    "-E",
+    "--resolve",
+    "--max-redirs",
+];
+
+/// Converts a curl command line into a hammer configuration with a single entry.
+pub fn import(command: &[String], options: &ImportOptions) -> Result<ImportedFile> {
+    let words = match command {
+        [single] => split(single)?,
+        many => many.to_vec(),
+    };
+    let mut words = words.into_iter().peekable();
+    if words.peek().map(String::as_str) == Some("curl") {
+        words.next();
+    }
+
+    let mut url = None;
+    let mut method = None;
+    let mut data: Vec<String> = vec![];
+    let mut get = false;
+    let mut headers: Vec<(String, String)> = vec![];
+
+    while let Some(word) = words.next() {
+        if !word.starts_with('-') || word == "-" {
+            url = Some(word);
+            continue;
+        }
+
+        if !word.is_char_boundary(2) {
+            bail!("Unsupported flag {word}");
+        }
+
+        // Short flags may have their value attached, like -XPOST
+        let (flag, attached) = match word.strip_prefix("--") {
+            Some(_) => match word.split_once('=') {
+                Some((flag, value)) if !value.is_empty() => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (word, None),
+            },
+            None if word.len() > 2 && !IGNORED_FLAGS.contains(&&word[..2]) => {
+                (word[..2].to_string(), Some(word[2..].to_string()))
+            }
+            None if word.len() > 2 => {
+                // A cluster of flags like -sSL
+                if word[1..]
+                    .chars()
+                    .all(|c| IGNORED_FLAGS.contains(&format!("-{c}").as_str()))
+                {
+                    continue;
+                }
+                bail!("Unsupported flag cluster {word}");
+            }
+            None => (word, None),
+        };
+        let mut value = || {
+            attached
+                .clone()
+                .or_else(|| words.next())
+                .ok_or_else(|| anyhow!("Missing value for {flag}"))
+        };
+
+        match flag.as_str() {
+            "-X" | "--request" => method = Some(value()?),
+            "-H" | "--header" => {
+                let header = value()?;
+                let (name, value) = header
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Invalid header {header}"))?;
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+            "-d" | "--data" | "--data-ascii" => {
+                let value = value()?;
+                data.push(match value.strip_prefix('@') {
+                    Some(path) => read_data(path)?.replace(['\r', '\n'], ""),
+                    None => value,
+                });
+            }
+            "--data-binary" => {
+                let value = value()?;
+                data.push(match value.strip_prefix('@') {
+                    Some(path) => read_data(path)?,
+                    None => value,
+                });
+            }
+            "--data-raw" => data.push(value()?),
+            "--data-urlencode" => {
+                let value = value()?;
+                data.push(match value.split_once('=') {
+                    Some((name, content)) => format!("{name}={}", urlencoding::encode(content)),
+                    None => urlencoding::encode(&value).into_owned(),
+                });
+            }
+            "--json" => {
+                data.push(value()?);
+                headers.push(("Content-Type".to_string(), "application/json".to_string()));
+                headers.push(("Accept".to_string(), "application/json".to_string()));
+            }
+            "-b" | "--cookie" => {
+                let value = value()?;
+                if !value.contains('=') {
+                    bail!("Reading cookies from a file is not supported");
+                }
+                headers.push(("Cookie".to_string(), value));
+            }
+            "-u" | "--user" => {
+                let user = value()?;
+                if !user.contains(':') {
+                    bail!("Basic authentication without a password is not supported, use {flag} {user}:<password>");
+                }
+                let credentials = base64::engine::general_purpose::STANDARD.encode(user);
+                headers.push(("Authorization".to_string(), format!("Basic {credentials}")));
+            }
+            "-A" | "--user-agent" => headers.push(("User-Agent".to_string(), value()?)),
+            "-e" | "--referer" => headers.push(("Referer".to_string(), value()?)),
+            "--compressed" => headers.push((
+                "Accept-Encoding".to_string(),
+                "deflate, gzip, br".to_string(),
+            )),
+            "-I" | "--head" => method = Some("HEAD".to_string()),
+            "-G" | "--get" => get = true,
+            "--url" => url = Some(value()?),
+            "-F" | "--form" => bail!("Multipart forms are not supported"),
+            flag if IGNORED_FLAGS.contains(&flag) => (),
+            flag if IGNORED_VALUE_FLAGS.contains(&flag) => {
+                value()?;
+            }
+            flag => bail!("Unsupported flag {flag}"),
+        }
+    }
+
+    let mut url = url.ok_or_else(|| anyhow!("No URL given"))?;
+    let data = (!data.is_empty()).then(|| data.join("&"));
+
+    let mut body = None;
+    match data {
+        Some(data) if get => {
+            url.push(if url.contains('?') { '&' } else { '?' });
+            url.push_str(&data);
+        }
+        Some(data) => {
+            if !headers
+                .iter()
+                .any(|(n, _)| n.eq_ignore_ascii_case("content-type"))
+            {
+                headers.push((
+                    "Content-Type".to_string(),
+                    "application/x-www-form-urlencoded".to_string(),
+                ));
+            }
+            body = Some(data);
+        }
+        None => (),
+    }
+
+    let method = method.unwrap_or_else(|| match body {
+        Some(_) => "POST".to_string(),
+        None => "GET".to_string(),
+    });
+
+    let mut entry = ImportedEntry::new(method, escape(&url), options.count);
+    for (name, value) in headers {
+        entry.add_header(&name, &value);
+    }
+    if let Some(body) = body {
+        entry.set_body(&body);
+    }
+
+    Ok(ImportedFile {
+        hammer: vec![entry],
+        ..Default::default()
+    })
+}
+
+fn read_data(path: &str) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("Could not read data from {path}"))
+}
+
+/// Splits a command line into words like a POSIX shell would, also understanding the `$'...'`
+/// quoting used by browsers when copying requests as curl commands.
+fn split(command: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => match chars.next() {
+                // Line continuation
+                Some('\n') => (),
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => bail!("Unexpected end of command after '\\'"),
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("Unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => (),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("Unterminated double quote"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("Unterminated double quote"),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                // `\x` escapes are bytes that may be part of a multi-byte character
+                let mut bytes = vec![];
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => push_char(&mut bytes, '\n'),
+                            Some('t') => push_char(&mut bytes, '\t'),
+                            Some('r') => push_char(&mut bytes, '\r'),
+                            Some('0') => push_char(&mut bytes, '\0'),
+                            Some('x') => {
+                                let hex: String = chars.by_ref().take(2).collect();
+                                let byte = u8::from_str_radix(&hex, 16)
+                                    .with_context(|| format!("Invalid escape \\x{hex}"))?;
+                                bytes.push(byte);
+                            }
+                            Some('u') => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                let chr = u32::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| anyhow!("Invalid escape \\u{hex}"))?;
+                                push_char(&mut bytes, chr);
+                            }
+                            Some(c) => push_char(&mut bytes, c),
+                            None => bail!("Unterminated $' quote"),
+                        },
+                        Some(c) => push_char(&mut bytes, c),
+                        None => bail!("Unterminated $' quote"),
+                    }
+                }
+                let quoted =
+                    String::from_utf8(bytes).context("Escapes in $' quote are not valid UTF-8")?;
+                word.get_or_insert_with(String::new).push_str(&quoted);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+fn push_char(bytes: &mut Vec<u8>, c: char) {
+    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use super::{escape, ImportedEntry, ImportedFile};
+use crate::cli::ImportOptions;
+
+#[derive(Deserialize)]
+struct Har {
+    log: Log,
+}
+
+#[derive(Deserialize)]
+struct Log {
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    request: Request,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<NameValue>,
+    #[serde(default)]
+    cookies: Vec<NameValue>,
+    post_data: Option<PostData>,
+}
+
+#[derive(Deserialize)]
+struct NameValue {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+    mime_type: Option<String>,
+    text: Option<String>,
+}
+
+/// Converts the requests recorded in a HAR file into a hammer configuration.
+pub fn import(text: &str, options: &ImportOptions) -> Result<ImportedFile> {
+    let har: Har = serde_json::from_str(text).context("Could not parse HAR file")?;
+
+    let mut file = ImportedFile::default();
+    for Entry { request } in har.log.entries {
+        let mut entry = ImportedEntry::new(request.method, escape(&request.url), options.count);
+
+        for cookie in request.cookies.iter() {
+            entry.add_cookie(&cookie.name, &cookie.value);
+        }
+        for header in request.headers.iter() {
+            entry.add_header(&header.name, &header.value);
+        }
+
+        if let Some(post_data) = request.post_data {
+            if let Some(mime_type) = post_data.mime_type.filter(|x| !x.is_empty()) {
+                if !entry
+                    .headers
+                    .keys()
+                    .any(|k| k.eq_ignore_ascii_case("content-type"))
+                {
+                    entry.add_header("Content-Type", &mime_type);
+                }
+            }
+            entry.set_body(post_data.text.as_deref().unwrap_or_default());
+        }
+
+        file.hammer.push(entry);
+    }
+
+    file.factor_out_common();
+    Ok(file)
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::cli::ImportOptions;
+
+pub mod curl;
+pub mod har;
+pub mod openapi;
+
+/// Headers that are managed by the HTTP client and should not end up in generated configs.
+const SKIPPED_HEADERS: &[&str] = &["host", "content-length", "connection", "cookie"];
+
+/// A hammer configuration file produced by one of the importers.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportedFile {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub cookies: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub resources: BTreeMap<String, String>,
+    pub hammer: Vec<ImportedEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportedEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub method: String,
+    pub uri: String,
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Imported cookies are already encoded, so this is `false` for entries that have cookies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encode_cookies: Option<bool>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub query: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub cookies: BTreeMap<String, String>,
+}
+
+impl ImportedEntry {
+    /// Creates an entry, `uri` is interpolated so it must already be escaped.
+    pub fn new(method: String, uri: String, count: u64) -> Self {
+        Self {
+            name: None,
+            method,
+            uri,
+            count,
+            body: None,
+            encode_cookies: None,
+            query: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            cookies: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a header to the entry, `Cookie` headers are split into the `cookies` table and
+    /// headers that the client sets by itself are ignored.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        let lowercase = name.to_ascii_lowercase();
+        if lowercase == "cookie" {
+            for (name, value) in split_cookie_header(value) {
+                self.add_cookie(name, value);
+            }
+        }
+        if lowercase.starts_with(':') || SKIPPED_HEADERS.contains(&lowercase.as_str()) {
+            return;
+        }
+
+        self.headers.insert(name.to_string(), escape(value));
+    }
+
+    /// Adds a cookie to the entry, it is sent exactly as given.
+    pub fn add_cookie(&mut self, name: &str, value: &str) {
+        self.cookies.insert(name.to_string(), escape(value));
+        self.encode_cookies = Some(false);
+    }
+
+    pub fn set_body(&mut self, body: &str) {
+        if !body.is_empty() {
+            self.body = Some(escape(body));
+        }
+    }
+}
+
+impl ImportedFile {
+    /// Moves headers and cookies that have the same value in every entry into the global tables.
+    pub fn factor_out_common(&mut self) {
+        if self.hammer.len() < 2 {
+            return;
+        }
+
+        fn common(maps: Vec<&BTreeMap<String, String>>) -> BTreeMap<String, String> {
+            let (first, rest) = maps.split_first().unwrap();
+            first
+                .iter()
+                .filter(|(k, v)| rest.iter().all(|m| m.get(*k) == Some(v)))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        }
+
+        let headers = common(self.hammer.iter().map(|e| &e.headers).collect());
+        let cookies = common(self.hammer.iter().map(|e| &e.cookies).collect());
+
+        for entry in self.hammer.iter_mut() {
+            entry.headers.retain(|k, _| !headers.contains_key(k));
+            entry.cookies.retain(|k, _| !cookies.contains_key(k));
+        }
+
+        self.headers.extend(headers);
+        self.cookies.extend(cookies);
+    }
+
+    pub fn write(&self, options: &ImportOptions) -> Result<()> {
+        let text = toml::to_string_pretty(self).context("Could not serialize configuration")?;
+
+        match options.output {
+            Some(ref path) => std::fs::write(path, text)
+                .with_context(|| format!("Could not write to {}", path.display())),
+            None => {
+                print!("{text}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Splits the value of a `Cookie` header into name, value pairs.
+fn split_cookie_header(value: &str) -> impl Iterator<Item = (&str, &str)> {
+    value.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        Some((name.trim(), value.trim()))
+    })
+}
+
+/// Escapes a string so that it is not treated as a format string when read back.
+pub fn escape(value: &str) -> String {
+    value.replace('$', "$$")
+}
@@ -0,0 +1,348 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Map, Value};
+
+use super::{escape, ImportedEntry, ImportedFile};
+use crate::cli::ImportOptions;
+
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Schemas nested deeper than this are replaced with `null`, this is mostly to avoid infinite
+/// recursion on recursive schemas.
+const MAX_DEPTH: usize = 8;
+
+/// Converts every operation in an OpenAPI 3 specification into a hammer entry.
+pub fn import(text: &str, server: Option<&str>, options: &ImportOptions) -> Result<ImportedFile> {
+    // JSON is a subset of YAML so this handles both
+    let spec: Value =
+        serde_yaml::from_str(text).context("Could not parse OpenAPI specification")?;
+
+    let base = match server {
+        Some(server) => server.to_string(),
+        None => server_url(&spec)?,
+    };
+    let base = base.trim_end_matches('/');
+    if !base.contains("://") {
+        bail!("Server URL {base} is not absolute, specify one with --server");
+    }
+
+    let mut file = ImportedFile::default();
+    let paths = spec
+        .get("paths")
+        .and_then(Value::as_object)
+        .ok_or_else(|| anyhow!("Specification does not contain any paths"))?;
+
+    for (path, item) in paths {
+        let item = resolve(&spec, item)?;
+
+        for &method in METHODS {
+            let Some(operation) = item.get(method) else {
+                continue;
+            };
+            let entry = operation_entry(
+                &spec, &mut file, base, path, method, item, operation, options,
+            )
+            .with_context(|| format!("Could not import {} {path}", method.to_uppercase()))?;
+            file.hammer.push(entry);
+        }
+    }
+
+    file.factor_out_common();
+    Ok(file)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn operation_entry(
+    spec: &Value,
+    file: &mut ImportedFile,
+    base: &str,
+    path: &str,
+    method: &str,
+    item: &Value,
+    operation: &Value,
+    options: &ImportOptions,
+) -> Result<ImportedEntry> {
+    let mut path = path.to_string();
+    let mut query = vec![];
+    let mut headers = vec![];
+    let mut cookies = vec![];
+
+    // Parameters of the operation override ones with the same name defined on the path
+    let mut parameters: Vec<&Value> = vec![];
+    for parameter in [item, operation]
+        .iter()
+        .filter_map(|x| x.get("parameters").and_then(Value::as_array))
+        .flatten()
+    {
+        let parameter = resolve(spec, parameter)?;
+        parameters.retain(|p| {
+            p.get("name") != parameter.get("name") || p.get("in") != parameter.get("in")
+        });
+        parameters.push(parameter);
+    }
+
+    for parameter in parameters {
+        let name = parameter
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Parameter without a name"))?;
+        let location = parameter
+            .get("in")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let required = parameter
+            .get("required")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        if !required && location != "path" {
+            continue;
+        }
+
+        let value = scalar(&parameter_example(spec, parameter)?);
+        match location {
+            "path" => path = path.replace(&format!("{{{name}}}"), &urlencoding::encode(&value)),
+            "query" => query.push((name.to_string(), escape(&value))),
+            "header" => headers.push((name.to_string(), escape(&value))),
+            "cookie" => cookies.push((name.to_string(), escape(&value))),
+            other => bail!("Unknown parameter location {other}"),
+        }
+    }
+
+    let security = operation
+        .get("security")
+        .or_else(|| spec.get("security"))
+        .and_then(Value::as_array)
+        .and_then(|x| x.first())
+        .and_then(Value::as_object);
+    for scheme_name in security.into_iter().flat_map(|x| x.keys()) {
+        let scheme = spec
+            .pointer(&format!(
+                "/components/securitySchemes/{}",
+                pointer_escape(scheme_name)
+            ))
+            .ok_or_else(|| anyhow!("Security scheme {scheme_name} does not exist"))?;
+        let scheme = resolve(spec, scheme)?;
+        let placeholder = format!("${{resources.{scheme_name}}}");
+
+        match scheme.get("type").and_then(Value::as_str) {
+            Some("http") => {
+                let kind = match scheme.get("scheme").and_then(Value::as_str) {
+                    Some(s) if s.eq_ignore_ascii_case("basic") => "Basic",
+                    _ => "Bearer",
+                };
+                headers.push(("Authorization".to_string(), format!("{kind} {placeholder}")));
+            }
+            Some("oauth2" | "openIdConnect") => {
+                headers.push(("Authorization".to_string(), format!("Bearer {placeholder}")));
+            }
+            Some("apiKey") => {
+                let name = scheme
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| anyhow!("API key security scheme {scheme_name} has no name"))?;
+                match scheme.get("in").and_then(Value::as_str) {
+                    Some("query") => query.push((name.to_string(), placeholder)),
+                    Some("cookie") => cookies.push((name.to_string(), placeholder)),
+                    _ => headers.push((name.to_string(), placeholder)),
+                }
+            }
+            other => bail!("Unsupported security scheme type {other:?}"),
+        }
+
+        file.resources
+            .entry(scheme_name.clone())
+            .or_insert_with(|| "REPLACE_ME".to_string());
+    }
+
+    let uri = format!("{}{}", escape(base), escape(&path));
+    let mut entry = ImportedEntry::new(method.to_uppercase(), uri, options.count);
+    entry.name = operation
+        .get("operationId")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    // These may contain placeholders so they are inserted as is
+    entry.query.extend(query);
+    entry.headers.extend(headers);
+    entry.cookies.extend(cookies);
+
+    if let Some(body) = operation.get("requestBody") {
+        let body = resolve(spec, body)?;
+        let content = body.get("content").and_then(Value::as_object);
+        let json = content.and_then(|c| {
+            c.iter()
+                .find(|(t, _)| *t == "application/json" || t.ends_with("+json"))
+        });
+
+        if let Some((content_type, media)) = json {
+            let example = match media.get("example") {
+                Some(example) => example.clone(),
+                None => match first_example(spec, media)? {
+                    Some(example) => example,
+                    None => schema_example(spec, media.get("schema").unwrap_or(&Value::Null), 0)?,
+                },
+            };
+            entry.add_header("Content-Type", content_type);
+            entry.set_body(&serde_json::to_string_pretty(&example)?);
+        } else if let Some((content_type, _)) = content.and_then(|c| c.iter().next()) {
+            entry.add_header("Content-Type", content_type);
+        }
+    }
+
+    Ok(entry)
+}
+
+fn server_url(spec: &Value) -> Result<String> {
+    let server = spec
+        .get("servers")
+        .and_then(Value::as_array)
+        .and_then(|x| x.first())
+        .ok_or_else(|| {
+            anyhow!("Specification does not define any servers, specify one with --server")
+        })?;
+    let mut url = server
+        .get("url")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("Server does not have an url"))?
+        .to_string();
+
+    for (name, variable) in server
+        .get("variables")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        if let Some(default) = variable.get("default").and_then(Value::as_str) {
+            url = url.replace(&format!("{{{name}}}"), default);
+        }
+    }
+
+    Ok(url)
+}
+
+/// Follows `$ref`s until a value that is not a reference is reached.
+fn resolve<'a>(spec: &'a Value, mut value: &'a Value) -> Result<&'a Value> {
+    for _ in 0..MAX_DEPTH {
+        let Some(reference) = value.get("$ref").and_then(Value::as_str) else {
+            return Ok(value);
+        };
+        let pointer = reference
+            .strip_prefix('#')
+            .ok_or_else(|| anyhow!("External reference {reference} is not supported"))?;
+        value = spec
+            .pointer(pointer)
+            .ok_or_else(|| anyhow!("Reference {reference} does not exist"))?;
+    }
+
+    bail!("Too many nested references")
+}
+
+fn pointer_escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn first_example(spec: &Value, object: &Value) -> Result<Option<Value>> {
+    let Some(example) = object
+        .get("examples")
+        .and_then(Value::as_object)
+        .and_then(|x| x.values().next())
+    else {
+        return Ok(None);
+    };
+
+    Ok(resolve(spec, example)?.get("value").cloned())
+}
+
+fn parameter_example(spec: &Value, parameter: &Value) -> Result<Value> {
+    if let Some(example) = parameter.get("example") {
+        return Ok(example.clone());
+    }
+    if let Some(example) = first_example(spec, parameter)? {
+        return Ok(example);
+    }
+    schema_example(spec, parameter.get("schema").unwrap_or(&Value::Null), 0)
+}
+
+/// Generates an example value that matches a JSON schema.
+fn schema_example(spec: &Value, schema: &Value, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Ok(Value::Null);
+    }
+    let schema = resolve(spec, schema)?;
+
+    for key in ["example", "default", "const"] {
+        if let Some(value) = schema.get(key) {
+            return Ok(value.clone());
+        }
+    }
+    if let Some(value) = schema
+        .get("enum")
+        .and_then(Value::as_array)
+        .and_then(|x| x.first())
+    {
+        return Ok(value.clone());
+    }
+    if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+        let mut merged = Map::new();
+        for schema in all {
+            match schema_example(spec, schema, depth + 1)? {
+                Value::Object(object) => merged.extend(object),
+                other => return Ok(other),
+            }
+        }
+        return Ok(Value::Object(merged));
+    }
+    if let Some(first) = ["oneOf", "anyOf"].iter().find_map(|k| {
+        schema
+            .get(k)
+            .and_then(Value::as_array)
+            .and_then(|x| x.first())
+    }) {
+        return schema_example(spec, first, depth + 1);
+    }
+
+    let kind = match schema.get("type") {
+        Some(Value::Array(types)) => types.iter().find_map(Value::as_str),
+        Some(kind) => kind.as_str(),
+        None if schema.get("properties").is_some() => Some("object"),
+        None => None,
+    };
+
+    Ok(match kind {
+        Some("object") => Value::Object(
+            schema
+                .get("properties")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .map(|(name, schema)| Ok((name.clone(), schema_example(spec, schema, depth + 1)?)))
+                .collect::<Result<_>>()?,
+        ),
+        Some("array") => json!([schema_example(
+            spec,
+            schema.get("items").unwrap_or(&Value::Null),
+            depth + 1
+        )?]),
+        Some("integer") => json!(1),
+        Some("number") => json!(1.5),
+        Some("boolean") => json!(true),
+        Some("string") => json!(match schema.get("format").and_then(Value::as_str) {
+            Some("date-time") => "2000-01-01T00:00:00Z",
+            Some("date") => "2000-01-01",
+            Some("uuid") => "00000000-0000-0000-0000-000000000000",
+            Some("email") => "user@example.com",
+            Some("uri") => "https://example.com",
+            _ => "string",
+        }),
+        _ => Value::Null,
+    })
+}
+
+/// Formats a JSON value the way it would appear in a path, query or header.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(scalar).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     collections::VecDeque,
-    fs::File,
-    io::{Read, Write},
+    io::Write,
+    path::Path,
     process::ExitCode,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
@@ -11,14 +11,22 @@ use std::{
 
 use anyhow::{bail, Context, Result};
 use clap::Parser;
-use config::{eval::Evaluator, HammerFile};
+use config::{
+    eval::{Evaluator, Scope},
+    feeder::{Exhausted, Feeder},
+    HammerFile, RequestInfo,
+};
 use hyper::{client::connect::Connect, Client};
 
+mod check;
 mod cli;
 mod config;
 mod cookie;
-use cli::Args;
-use tokio::sync::Mutex;
+mod import;
+mod render;
+mod replay;
+use cli::{Args, Command, ImportCommand};
+use cookie::CookieJars;
 
 const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), " v", env!("CARGO_PKG_VERSION"));
 
@@ -100,41 +108,114 @@ fn hyper_connector() -> impl Connect + Clone {
 
 async fn real_main() -> Result<ExitCode> {
     let args = Args::parse();
+    let client: Client<_, hyper::Body> = hyper::Client::builder().build(hyper_connector());
 
-    let mut buf = String::new();
-    {
-        let mut file = File::open(&args.config).context("Could not open urls file")?;
-        file.read_to_string(&mut buf)
-            .context("Could not read urls file")?;
-    }
-
-    let config = HammerFile::parse_toml(&buf).context("Could not parse urls file")?;
+    let config_path = match args.command {
+        Some(Command::Check { config }) => {
+            return check::check(&config, args.config_format, &args.vars)
+        }
+        Some(Command::Replay(replay)) => {
+            return replay::replay(client, replay, args.tasks, args.verbose).await
+        }
+        Some(Command::Import(ImportCommand::Har { file, options })) => {
+            let text = std::fs::read_to_string(file).context("Could not read HAR file")?;
+            import::har::import(&text, &options)?.write(&options)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Import(ImportCommand::OpenApi {
+            file,
+            server,
+            options,
+        })) => {
+            let text = std::fs::read_to_string(file).context("Could not read specification")?;
+            import::openapi::import(&text, server.as_deref(), &options)?.write(&options)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::FromCurl { command, options }) => {
+            import::curl::import(&command, &options)
+                .context("Could not convert curl command")?
+                .write(&options)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => args
+            .config
+            .expect("clap should require a config file if no subcommand is given"),
+    };
+
+    let buf = HammerFile::read(&config_path)?;
+    let format = HammerFile::format(&config_path, args.config_format);
+    let base_dir = config_path.parent().unwrap_or(Path::new("."));
+    let mut config =
+        HammerFile::parse(&buf, format, base_dir).context("Could not parse urls file")?;
+    config.apply_overrides(&args.overrides)?;
+    // Resources stay locked while they are evaluated, so tasks evaluating different resources of
+    // a cycle would wait for each other forever
+    check::reject_cycles(&config)?;
+    let target = args
+        .overrides
+        .target_base_url
+        .as_deref()
+        .map(parse_target_base_url)
+        .transpose()?;
 
-    let client: Client<_, hyper::Body> = hyper::Client::builder().build(hyper_connector());
     let evaluator = Arc::new(Evaluator {
         client: client.clone(),
         verbose: args.verbose,
-        resources: config
-            .resources
+        resources: config.resources,
+        resource_states: Default::default(),
+        feeders: config
+            .feeders
             .into_iter()
-            .map(|(n, v)| (n, Mutex::new(v)))
-            .collect(),
-
+            .map(|(n, f)| {
+                Feeder::load(n.clone(), f, base_dir)
+                    .with_context(|| format!("Failed to load feeder {n}"))
+                    .map(|f| (n, f))
+            })
+            .collect::<Result<_>>()?,
+        vars: args.vars.into_iter().collect(),
+        target,
         request_cache: Default::default(),
+        cookie_jar: config.cookie_jar.map(CookieJars::new),
+        digest_challenges: Default::default(),
     });
 
     for info in config.hammer {
         let todo = Arc::new(AtomicU64::from(info.count));
         let error_encountered = Arc::new(AtomicBool::new(false));
+        let exhausted = Arc::new(AtomicBool::new(false));
 
         if args.verbose > 0 {
             eprintln!("Evaluating {}", info.name);
         }
-        let request = info
-            .request
-            .build(evaluator.clone())
-            .await
-            .with_context(|| format!("Failed to evaulate request for {}", info.name))?;
+        // The first request is always evaluated up front so that errors in the configuration are
+        // reported before any hammering starts, it then becomes the first request of task 0.
+        let scope = Scope::new(0);
+        let request = match info.request.clone().build(evaluator.clone(), &scope).await {
+            Err(e) if is_exhausted(&e) => {
+                eprintln!("Hammering {} \x1b[33;1mskipped\x1b[0m: {e}", info.name);
+                continue;
+            }
+            result => {
+                result.with_context(|| format!("Failed to evaulate request for {}", info.name))?
+            }
+        };
+        if args.print_curl {
+            let secrets = match args.redact {
+                true => Some(evaluator.resolved_resources().await),
+                false => None,
+            };
+            println!("# {}", info.name);
+            let command = render::curl(&request, secrets.as_deref())
+                .with_context(|| format!("Failed to render request for {}", info.name))?;
+            println!("{command}");
+            continue;
+        }
+
+        // If the request depends on values that change between requests, including the cookies
+        // in the cookie jar, it has to be rebuilt every time, otherwise the same request can be
+        // reused.
+        let template = (scope.is_dynamic() || evaluator.cookie_jar.is_some())
+            .then(|| Arc::new(info.request.clone()));
 
         let mut handles = vec![];
 
@@ -142,17 +223,21 @@ async fn real_main() -> Result<ExitCode> {
             .max_concurrency
             .map(|x| x.min(args.tasks))
             .unwrap_or(args.tasks);
-        for _ in 0..tasks {
+        let mut scope = Some(scope);
+        for tidx in 0..tasks as usize {
             let request = request.clone();
+            let mut first = (tidx == 0).then(|| (request.clone(), scope.take().unwrap()));
+            let template = template.clone();
             let uri = request.uri().clone();
             let method = request.method().clone();
-            let client = client.clone();
+            let evaluator = evaluator.clone();
             let todo = todo.clone();
             let error_encountered = error_encountered.clone();
             let error_encountered2 = error_encountered.clone();
+            let exhausted = exhausted.clone();
 
             handles.push(tokio::spawn(async move {
-                let result = (|| async move {
+                let result = async move {
                     let mut stats = HammerStats::default();
 
                     while todo
@@ -160,11 +245,42 @@ async fn real_main() -> Result<ExitCode> {
                         .is_ok()
                         && !error_encountered2.load(Ordering::Relaxed)
                     {
-                        let request = request.clone().into();
-
-                        let start = std::time::Instant::now();
-
-                        let response = client.request(request).await?;
+                        let (request, scope) = match &template {
+                            None => (request.clone(), None),
+                            Some(template) => match first.take() {
+                                Some((request, scope)) => (request, Some(scope)),
+                                None => {
+                                    let scope = Scope::new(tidx);
+                                    match RequestInfo::clone(template)
+                                        .build(evaluator.clone(), &scope)
+                                        .await
+                                    {
+                                        Err(e) if is_exhausted(&e) => {
+                                            exhausted.store(true, Ordering::Release);
+                                            todo.store(0, Ordering::Release);
+                                            break;
+                                        }
+                                        result => (result?, Some(scope)),
+                                    }
+                                }
+                            },
+                        };
+
+                        let mut start = std::time::Instant::now();
+
+                        let mut response = evaluator.send(tidx, request).await?;
+
+                        // A resource used by the request may have to be refreshed after this
+                        // response, the request is then retried once with the new value.
+                        if let (Some(template), Some(scope)) = (&template, &scope) {
+                            if evaluator.refresh_on(scope, response.status()).await {
+                                let request = RequestInfo::clone(template)
+                                    .build(evaluator.clone(), scope)
+                                    .await?;
+                                start = std::time::Instant::now();
+                                response = evaluator.send(tidx, request).await?;
+                            }
+                        }
 
                         let responded = std::time::Instant::now();
 
@@ -184,7 +300,7 @@ async fn real_main() -> Result<ExitCode> {
                     }
 
                     Ok(stats) as anyhow::Result<HammerStats>
-                })()
+                }
                 .await;
 
                 if result.is_err() {
@@ -231,12 +347,30 @@ async fn real_main() -> Result<ExitCode> {
             tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
         }
 
+        let mut stats = HammerStats::default();
+        let mut failures = vec![];
+        for (tidx, handle) in handles.into_iter().enumerate() {
+            match handle.await? {
+                Ok(htr) => stats.append(htr),
+                Err(e) => failures.push((tidx, e)),
+            }
+        }
+
         if !error_encountered.load(Ordering::Acquire) {
-            eprintln!(
-                "\x1b[2KHammering {} \x1b[32;1m{count}/{count}\x1b[0m",
-                info.name,
-                count = info.count
-            );
+            if exhausted.load(Ordering::Acquire) {
+                eprintln!(
+                    "\x1b[2KHammering {} \x1b[33;1m{done}/{count}\x1b[0m (feeder exhausted)",
+                    info.name,
+                    done = stats.total.done,
+                    count = info.count
+                );
+            } else {
+                eprintln!(
+                    "\x1b[2KHammering {} \x1b[32;1m{count}/{count}\x1b[0m",
+                    info.name,
+                    count = info.count
+                );
+            }
         } else {
             let done = info.count - todo.load(Ordering::Acquire);
             eprintln!(
@@ -246,23 +380,20 @@ async fn real_main() -> Result<ExitCode> {
             );
         }
 
-        let mut stats = HammerStats::default();
-        let mut any_failed = false;
-        for (tidx, handle) in handles.into_iter().enumerate() {
-            match handle.await? {
-                Ok(htr) => stats.append(htr),
-                Err(e) => {
-                    eprintln!("    Task {} \x1b[31;1mfailed\x1b[0m: {e}", tidx + 1);
-                    any_failed = true;
-                }
+        if !failures.is_empty() {
+            for (tidx, e) in failures {
+                eprintln!("    Task {} \x1b[31;1mfailed\x1b[0m: {e}", tidx + 1);
             }
+            return Ok(ExitCode::FAILURE);
         }
 
-        if any_failed {
-            return Ok(ExitCode::FAILURE);
+        if stats.total.done == 0 {
+            continue;
         }
 
-        assert_eq!(stats.total.done, info.count);
+        if !exhausted.load(Ordering::Acquire) {
+            assert_eq!(stats.total.done, info.count);
+        }
 
         println!(
             "    Initial response: min {:.2}ms avg {:.2}ms max {:.2}ms",
@@ -282,6 +413,23 @@ async fn real_main() -> Result<ExitCode> {
     Ok(ExitCode::SUCCESS)
 }
 
+fn parse_target_base_url(url: &str) -> Result<hyper::Uri> {
+    let uri = url
+        .parse::<hyper::Uri>()
+        .with_context(|| format!("{url} is not a valid url"))?;
+    if uri.scheme().is_none() || uri.authority().is_none() {
+        bail!("Target base url {url} must contain a scheme and a host");
+    }
+    if !matches!(uri.path(), "" | "/") || uri.query().is_some() {
+        bail!("Target base url {url} must not contain a path or query");
+    }
+    Ok(uri)
+}
+
+fn is_exhausted(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| e.is::<Exhausted>())
+}
+
 #[tokio::main]
 async fn main() -> ExitCode {
     match real_main().await {
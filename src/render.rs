@@ -0,0 +1,140 @@
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use hyper::{header, HeaderMap, Request};
+
+use crate::config::{sign::Signer, AlmostRequest};
+
+const REDACTED: &str = "REDACTED";
+
+/// Headers whose values are always redacted.
+const SENSITIVE_HEADERS: &[header::HeaderName] = &[
+    header::AUTHORIZATION,
+    header::PROXY_AUTHORIZATION,
+    header::COOKIE,
+];
+
+/// Renders a request as an equivalent curl command.
+///
+/// If `secrets` is given then the values of sensitive headers and all occurences of the
+/// strings in `secrets` are replaced with a placeholder.
+pub fn curl(request: &AlmostRequest, secrets: Option<&[String]>) -> Result<String> {
+    let body = request.body().to_string();
+    let digest = request.digest().cloned();
+    let signer = request.signer().cloned();
+    // Going through the conversion ensures headers added while sending are included
+    let mut request: Request<hyper::Body> = request.clone().into();
+
+    // An HMAC signature can only be shown as it would be if the request was sent right now, while
+    // curl can compute an AWS signature by itself
+    if let Some(ref signer @ Signer::Hmac { .. }) = signer {
+        let (mut parts, _) = request.into_parts();
+        signer
+            .sign(
+                &parts.method,
+                &parts.uri,
+                &mut parts.headers,
+                &body,
+                SystemTime::now(),
+            )
+            .context("Failed to sign request")?;
+        request = Request::from_parts(parts, hyper::Body::empty());
+    }
+
+    let redact = |value: &str| -> String {
+        let mut value = value.to_string();
+        for secret in secrets.into_iter().flatten().filter(|x| !x.is_empty()) {
+            value = value.replace(secret.as_str(), REDACTED);
+        }
+        value
+    };
+
+    let mut command = format!(
+        "curl -X {} {}",
+        request.method(),
+        quote(&redact(&request.uri().to_string()))
+    );
+
+    for (name, value) in sorted(request.headers()) {
+        // Requests without cookies are sent with an empty `Cookie` header, which curl does not need
+        if name == header::COOKIE && value.is_empty() {
+            continue;
+        }
+        let value = String::from_utf8_lossy(value.as_bytes());
+        let value = match secrets {
+            Some(_) if name == header::COOKIE => value
+                .split("; ")
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, _)) => format!("{name}={REDACTED}"),
+                    None => pair.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("; "),
+            Some(_) if SENSITIVE_HEADERS.contains(name) || is_sensitive(name.as_str()) => {
+                REDACTED.to_string()
+            }
+            _ => redact(&value),
+        };
+
+        command.push_str(" \\\n  -H ");
+        command.push_str(&quote(&format!("{name}: {value}")));
+    }
+
+    if let Some(credentials) = digest {
+        let password = match secrets {
+            Some(_) => REDACTED.to_string(),
+            None => credentials.password.clone(),
+        };
+        command.push_str(" \\\n  --digest -u ");
+        command.push_str(&quote(&format!("{}:{password}", credentials.user)));
+    }
+
+    if let Some(Signer::AwsSigv4 {
+        access_key,
+        secret_key,
+        session_token,
+        region,
+        service,
+    }) = signer
+    {
+        let (secret_key, session_token) = match secrets {
+            Some(_) => (
+                REDACTED.to_string(),
+                session_token.map(|_| REDACTED.to_string()),
+            ),
+            None => (secret_key, session_token),
+        };
+        command.push_str(" \\\n  --aws-sigv4 ");
+        command.push_str(&quote(&format!("aws:amz:{region}:{service}")));
+        command.push_str(" \\\n  -u ");
+        command.push_str(&quote(&format!("{access_key}:{secret_key}")));
+        if let Some(token) = session_token {
+            command.push_str(" \\\n  -H ");
+            command.push_str(&quote(&format!("x-amz-security-token: {token}")));
+        }
+    }
+
+    if !body.is_empty() {
+        command.push_str(" \\\n  --data-raw ");
+        command.push_str(&quote(&redact(&body)));
+    }
+
+    Ok(command)
+}
+
+fn is_sensitive(name: &str) -> bool {
+    ["token", "secret", "key", "password"]
+        .iter()
+        .any(|x| name.contains(x))
+}
+
+fn sorted(headers: &HeaderMap) -> Vec<(&header::HeaderName, &header::HeaderValue)> {
+    let mut headers = headers.iter().collect::<Vec<_>>();
+    headers.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    headers
+}
+
+/// Quotes a string so that a POSIX shell treats it as a single word.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::Path,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use hyper::{
+    client::connect::Connect,
+    header::{HeaderName, REFERER, USER_AGENT},
+    http::HeaderValue,
+    Client, HeaderMap, Method, StatusCode, Uri,
+};
+use serde::Deserialize;
+
+use crate::{
+    cli::{LogFormat, ReplayArgs},
+    config::AlmostRequest,
+    HammerStats,
+};
+
+/// A single request read from an access log.
+struct Record {
+    /// Unix timestamp of the request in seconds.
+    timestamp: Option<f64>,
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+    body: String,
+}
+
+#[derive(Default)]
+struct PatternStats {
+    timing: HammerStats,
+    statuses: BTreeMap<u16, u64>,
+    errors: u64,
+}
+
+pub async fn replay<C>(
+    client: Client<C>,
+    args: ReplayArgs,
+    tasks: u64,
+    verbose: u8,
+) -> Result<ExitCode>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let text = std::fs::read_to_string(&args.log).context("Could not read access log")?;
+    let (records, mut skipped) = parse_log(&text, resolve_format(args.format, &args.log));
+
+    let target = args.target.trim_end_matches('/');
+    let mut requests = vec![];
+    for (line, record) in records {
+        let uri: Uri = match format!("{target}{}", record.path).parse() {
+            Ok(uri) => uri,
+            Err(_) => {
+                eprintln!(
+                    "Line {line} \x1b[33;1mskipped\x1b[0m: {target}{} is not a valid uri",
+                    record.path
+                );
+                skipped += 1;
+                continue;
+            }
+        };
+        let key = format!("{} {}", record.method, path_pattern(&record.path));
+        if args.speed.is_some() && record.timestamp.is_none() {
+            bail!("Cannot replay with original timing, the log has no timestamps")
+        }
+
+        requests.push((
+            record.timestamp,
+            key,
+            AlmostRequest::new(record.method, uri, record.headers, record.body),
+        ));
+    }
+    if requests.is_empty() && skipped > 0 {
+        bail!("None of the {skipped} entries of the access log are valid");
+    }
+
+    let total = requests.len() as u64;
+    let first_timestamp = requests
+        .iter()
+        .filter_map(|x| x.0)
+        .min_by(f64::total_cmp)
+        .unwrap_or(0.0);
+    let requests = Arc::new(requests);
+    let results = Arc::new(Mutex::new(BTreeMap::<String, PatternStats>::new()));
+    let done = Arc::new(AtomicU64::new(0));
+
+    let replay_one = {
+        let results = results.clone();
+        let done = done.clone();
+        move |key: String, request: AlmostRequest| {
+            let client = client.clone();
+            let results = results.clone();
+            let done = done.clone();
+            async move {
+                let result = send(&client, request.clone()).await;
+                let mut results = results.lock().unwrap();
+                let stats = results.entry(key).or_default();
+                match result {
+                    Ok((status, response, total)) => {
+                        *stats.statuses.entry(status.as_u16()).or_default() += 1;
+                        stats.timing.response.add(response);
+                        stats.timing.total.add(total);
+                    }
+                    Err(e) => {
+                        if verbose > 0 {
+                            eprintln!("\x1b[2K{} {} failed: {e}", request.method(), request.uri());
+                        }
+                        stats.errors += 1;
+                    }
+                }
+                done.fetch_add(1, Ordering::Release);
+            }
+        }
+    };
+
+    let start = tokio::time::Instant::now();
+    let mut handles = vec![];
+    match args.speed {
+        None => {
+            let next = Arc::new(AtomicUsize::new(0));
+            for _ in 0..tasks.max(1) {
+                let next = next.clone();
+                let requests = requests.clone();
+                let replay_one = replay_one.clone();
+                handles.push(tokio::spawn(async move {
+                    while let Some((_, key, request)) =
+                        requests.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        replay_one(key.clone(), request.clone()).await;
+                    }
+                }));
+            }
+        }
+        Some(speed) => {
+            let requests = requests.clone();
+            handles.push(tokio::spawn(async move {
+                let mut sent = vec![];
+                for (timestamp, key, request) in requests.iter() {
+                    let offset = (timestamp.unwrap() - first_timestamp).max(0.0) / speed;
+                    tokio::time::sleep_until(start + Duration::from_secs_f64(offset)).await;
+                    sent.push(tokio::spawn(replay_one(key.clone(), request.clone())));
+                }
+                for handle in sent {
+                    handle.await.unwrap();
+                }
+            }));
+        }
+    }
+
+    loop {
+        let done = done.load(Ordering::Acquire);
+        if done == total {
+            break;
+        }
+
+        eprint!("\x1b[2KReplaying \x1b[33;1m{done}/{total}\x1b[0m\r");
+        std::io::stderr()
+            .flush()
+            .context("Could not flush stderr")?;
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    }
+
+    for handle in handles {
+        handle.await?;
+    }
+
+    eprintln!(
+        "\x1b[2KReplayed \x1b[32;1m{total}\x1b[0m requests in {:.2}s",
+        start.elapsed().as_secs_f64()
+    );
+    if skipped > 0 {
+        eprintln!("Skipped \x1b[33;1m{skipped}\x1b[0m invalid entries of the access log");
+    }
+
+    let mut any_failed = false;
+    for (key, stats) in results.lock().unwrap().iter() {
+        let statuses = stats
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{status}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        print!(
+            "{key} \x1b[33;1m{}\x1b[0m",
+            stats.timing.total.done + stats.errors
+        );
+        if stats.errors > 0 {
+            any_failed = true;
+            print!(" (\x1b[31;1m{} failed\x1b[0m)", stats.errors);
+        }
+        println!();
+
+        if stats.timing.total.done > 0 {
+            println!("    Statuses: {statuses}");
+            println!(
+                "    Initial response: min {:.2}ms avg {:.2}ms max {:.2}ms",
+                stats.timing.response.min_secs() * 1000.0,
+                stats.timing.response.avg_secs() * 1000.0,
+                stats.timing.response.max_secs() * 1000.0,
+            );
+            println!(
+                "    Whole body: min {:.2}ms avg {:.2}ms max {:.2}ms",
+                stats.timing.total.min_secs() * 1000.0,
+                stats.timing.total.avg_secs() * 1000.0,
+                stats.timing.total.max_secs() * 1000.0,
+            );
+        }
+    }
+
+    Ok(if any_failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+async fn send<C>(
+    client: &Client<C>,
+    request: AlmostRequest,
+) -> Result<(StatusCode, Duration, Duration)>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let start = std::time::Instant::now();
+    let response = client.request(request.into()).await?;
+    let responded = std::time::Instant::now();
+    let status = response.status();
+    hyper::body::to_bytes(response.into_body()).await?;
+    let end = std::time::Instant::now();
+
+    Ok((status, responded - start, end - start))
+}
+
+fn resolve_format(format: LogFormat, path: &Path) -> LogFormat {
+    match format {
+        LogFormat::Auto => match path.extension().and_then(|x| x.to_str()) {
+            Some("jsonl" | "ndjson") => LogFormat::Jsonl,
+            _ => LogFormat::Combined,
+        },
+        other => other,
+    }
+}
+
+/// Parses the entries of an access log along with their line numbers.
+///
+/// Real logs are full of lines that are not requests, like `"-"` request lines or TLS probes, so
+/// invalid lines are skipped with a warning instead of failing the replay. Also returns how many
+/// lines were skipped.
+fn parse_log(text: &str, format: LogFormat) -> (Vec<(usize, Record)>, u64) {
+    let mut records = vec![];
+    let mut skipped = 0;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match format {
+            LogFormat::Jsonl => parse_jsonl(line),
+            _ => parse_combined(line),
+        };
+        match record {
+            Ok(record) => records.push((i + 1, record)),
+            Err(e) => {
+                eprintln!("Line {} \x1b[33;1mskipped\x1b[0m: {e:#}", i + 1);
+                skipped += 1;
+            }
+        }
+    }
+    (records, skipped)
+}
+
+fn parse_jsonl(line: &str) -> Result<Record> {
+    #[derive(Deserialize)]
+    struct Raw {
+        #[serde(with = "crate::config::serde_http::method", default = "method_get")]
+        method: Method,
+        path: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default)]
+        body: String,
+        timestamp: Option<f64>,
+    }
+
+    fn method_get() -> Method {
+        Method::GET
+    }
+
+    let raw: Raw = serde_json::from_str(line)?;
+    let mut headers = HeaderMap::new();
+    for (name, value) in raw.headers {
+        headers.insert(
+            HeaderName::try_from(&name).with_context(|| format!("Invalid header name {name}"))?,
+            HeaderValue::try_from(&value)
+                .with_context(|| format!("Invalid value for header {name}"))?,
+        );
+    }
+
+    Ok(Record {
+        timestamp: raw.timestamp,
+        method: raw.method,
+        path: raw.path,
+        headers,
+        body: raw.body,
+    })
+}
+
+/// Parses a line in the combined log format, for example:
+/// `127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a.gif HTTP/1.0" 200 2326 "-" "curl/7.0"`
+fn parse_combined(line: &str) -> Result<Record> {
+    let (_, rest) = line
+        .split_once('[')
+        .ok_or_else(|| anyhow!("Missing timestamp"))?;
+    let (time, rest) = rest
+        .split_once(']')
+        .ok_or_else(|| anyhow!("Missing timestamp"))?;
+    let timestamp = parse_log_time(time).with_context(|| format!("Invalid timestamp {time}"))?;
+
+    let (request_line, rest) = quoted(rest).ok_or_else(|| anyhow!("Missing request line"))?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("Invalid request line {request_line}");
+    };
+
+    // Status and size are skipped, then come the optional referer and user agent.
+    let mut headers = HeaderMap::new();
+    if let Some((referer, rest)) = quoted(rest) {
+        if referer != "-" {
+            headers.insert(REFERER, HeaderValue::try_from(referer)?);
+        }
+        if let Some((agent, _)) = quoted(rest) {
+            if agent != "-" {
+                headers.insert(USER_AGENT, HeaderValue::try_from(agent)?);
+            }
+        }
+    }
+
+    Ok(Record {
+        timestamp: Some(timestamp),
+        method: method
+            .parse()
+            .map_err(|_| anyhow!("Invalid method {method}"))?,
+        path: path.to_string(),
+        headers,
+        body: String::new(),
+    })
+}
+
+/// Finds the next double quoted string in `text` and returns its unescaped contents along with
+/// the rest of the text.
+fn quoted(text: &str) -> Option<(String, &str)> {
+    let start = text.find('"')? + 1;
+    let mut value = String::new();
+    let mut chars = text[start..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            '"' => return Some((value, &text[start + i + 1..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Parses a timestamp like `10/Oct/2000:13:55:36 -0700` into seconds since the unix epoch.
+fn parse_log_time(time: &str) -> Option<f64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (datetime, zone) = time.split_once(' ').unwrap_or((time, "+0000"));
+    let mut parts = datetime.splitn(4, [':', '/']);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut clock = parts.next()?.split(':');
+    let hours: i64 = clock.next()?.parse().ok()?;
+    let minutes: i64 = clock.next()?.parse().ok()?;
+    let seconds: f64 = clock.next()?.parse().ok()?;
+
+    let sign = match zone.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let zone: i64 = zone.get(1..)?.parse().ok()?;
+    let zone_offset = sign * ((zone / 100) * 3600 + (zone % 100) * 60);
+
+    // Days since the epoch from a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some((days * 86400 + hours * 3600 + minutes * 60 - zone_offset) as f64 + seconds)
+}
+
+/// Turns a path into a pattern by stripping the query and replacing segments that look like
+/// identifiers with `:id`, so that stats for `/users/1` and `/users/2` end up together.
+fn path_pattern(path: &str) -> String {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    path.split('/')
+        .map(|segment| {
+            let is_id = !segment.is_empty()
+                && (segment.bytes().all(|b| b.is_ascii_digit())
+                    || (segment.len() >= 16
+                        && segment.bytes().all(|b| b.is_ascii_hexdigit() || b == b'-')));
+            if is_id {
+                ":id"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}